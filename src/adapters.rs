@@ -0,0 +1,315 @@
+use crate::*;
+
+const GAS_FOR_NFT_PAYOUT_VIEW: Gas = Gas(BASE_GAS.0 * 2u64);
+// default and highest, every receiver adds an `ft_transfer` to the resolve of a fungible token sale
+const MAX_LEN_PAYOUT: u32 = MAX_ROYALTY_RECEIVERS as u32;
+// keeps the gas of a purchase of an unknown contract bounded
+const MAX_NFT_TRANSFER_GAS: Gas = Gas(BASE_GAS.0 * 10u64);
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutSupport {
+    // probed with `nft_payout` before the next transfer, the sale is refunded if it fails
+    Unknown,
+    Supported,
+    // only set by admins, a failed `nft_payout` may be the token's own, like too many receivers
+    Unsupported,
+}
+
+/// How NFTs of a contract are transferred on a sale.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftAdapter {
+    pub payouts: PayoutSupport,
+    pub max_len_payout: u32,
+    pub transfer_gas: U64,
+}
+
+impl Default for NftAdapter {
+    fn default() -> Self {
+        Self {
+            payouts: PayoutSupport::Unknown,
+            max_len_payout: MAX_LEN_PAYOUT,
+            transfer_gas: U64::from(GAS_FOR_NFT_TRANSFER.0),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_nft_adapter(
+        &mut self,
+        nft_contract_id: AccountId,
+        payouts: PayoutSupport,
+        max_len_payout: Option<u32>,
+        transfer_gas: Option<U64>,
+    ) {
+        self.assert_role(Role::Moderator);
+        let adapter = NftAdapter {
+            payouts,
+            max_len_payout: max_len_payout.unwrap_or(MAX_LEN_PAYOUT),
+            transfer_gas: transfer_gas.unwrap_or_else(|| U64::from(GAS_FOR_NFT_TRANSFER.0)),
+        };
+        assert!(adapter.max_len_payout > 0 && adapter.max_len_payout <= MAX_LEN_PAYOUT,
+                "max_len_payout must be from 1 to {}", MAX_LEN_PAYOUT);
+        assert!(adapter.transfer_gas.0 <= MAX_NFT_TRANSFER_GAS.0,
+                "transfer_gas can't exceed {}", MAX_NFT_TRANSFER_GAS.0);
+        self.nft_adapters.insert(&nft_contract_id, &adapter);
+
+        MarketEvent::NftAdapterChange(vec![NftAdapterEvent {
+            nft_contract_id,
+            adapter: Some(adapter),
+            learned: false,
+        }]).emit();
+    }
+
+    /// Forgets the capabilities of `nft_contract_id`, they are probed again on its next sale.
+    pub fn remove_nft_adapter(&mut self, nft_contract_id: AccountId) {
+        self.assert_role(Role::Moderator);
+        self.nft_adapters.remove(&nft_contract_id)
+            .expect("NFT contract has no adapter.");
+
+        MarketEvent::NftAdapterChange(vec![NftAdapterEvent {
+            nft_contract_id,
+            adapter: None,
+            learned: false,
+        }]).emit();
+    }
+
+    pub fn get_nft_adapter(self, nft_contract_id: AccountId) -> NftAdapter {
+        self.nft_adapters.get(&nft_contract_id).unwrap_or_default()
+    }
+
+    /// Records what `nft_payout` told about the contract and goes on with the transfer locked
+    /// by `transfer_nft`. The buyer is refunded and the listing unlocked if the probe failed.
+    #[private]
+    pub fn resolve_payout_probe(
+        &mut self,
+        buyer_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
+        transfer_gas: U64,
+    ) -> PromiseOrValue<()> {
+        let nft_data = self.uid_to_data.get(&nft_uid.clone()).unwrap();
+        let adapter = match self.learn_payouts(&nft_data.nft_contract_id, price) {
+            Some(adapter) => adapter,
+            None => {
+                self.fail_probe(nft_uid, nft_data, buyer_id, price);
+                return PromiseOrValue::Value(());
+            }
+        };
+        // an admin may have raised it meanwhile, the callback only has gas for the probed one
+        let transfer_gas = U64::from(min(adapter.transfer_gas.0, transfer_gas.0));
+
+        PromiseOrValue::Promise(
+            Contract::send_nft(nft_uid, nft_data, buyer_id, price, &NftAdapter { transfer_gas, ..adapter })
+        )
+    }
+}
+
+impl Contract {
+    pub(crate) fn nft_adapter(&self, nft_contract_id: &AccountId) -> NftAdapter {
+        self.nft_adapters.get(nft_contract_id).unwrap_or_default()
+    }
+
+    // gas of the transfer and its `resolve_purchase`, after the probe if the payouts are unknown
+    pub(crate) fn gas_for_sale(adapter: &NftAdapter, ft_token_id: &Option<AccountId>) -> Gas {
+        let gas_for_send = Gas(adapter.transfer_gas.0 + Contract::gas_for_resolve(ft_token_id, adapter.max_len_payout).0);
+        if adapter.payouts == PayoutSupport::Unknown {
+            Gas(GAS_FOR_NFT_PAYOUT_VIEW.0 + gas_for_send.0 + BASE_GAS.0)
+        } else {
+            gas_for_send
+        }
+    }
+
+    pub(crate) fn probe_payouts(
+        nft_uid: TokenUID,
+        nft_data: TokenData,
+        buyer_id: AccountId,
+        price: U128,
+        adapter: &NftAdapter,
+    ) -> Promise {
+        let gas_for_resolve = Contract::gas_for_resolve(&nft_data.ft_token_id, adapter.max_len_payout);
+
+        Contract::payout_probe(&nft_data.nft_contract_id, &nft_data.token_id, price, adapter)
+            .then(ext_self::resolve_payout_probe(
+                buyer_id,
+                nft_uid,
+                price,
+                adapter.transfer_gas,
+                env::current_account_id(),
+                NO_DEPOSIT,
+                Gas(adapter.transfer_gas.0 + gas_for_resolve.0 + BASE_GAS.0),
+            ))
+    }
+
+    pub(crate) fn payout_probe(nft_contract_id: &AccountId, token_id: &TokenId, price: U128, adapter: &NftAdapter) -> Promise {
+        nft_contract::nft_payout(
+            token_id.clone(),
+            price,
+            adapter.max_len_payout,
+            nft_contract_id.clone(),
+            NO_DEPOSIT,
+            GAS_FOR_NFT_PAYOUT_VIEW,
+        )
+    }
+
+    // adapter the probed sale is sent with, none if `nft_payout` failed. Only the support is
+    // learned, a failure can't tell a missing method from a token it panics on
+    pub(crate) fn learn_payouts(&mut self, nft_contract_id: &AccountId, price: U128) -> Option<NftAdapter> {
+        let mut adapter = self.nft_adapter(nft_contract_id);
+        // an admin may have set the adapter meanwhile
+        if adapter.payouts != PayoutSupport::Unknown {
+            return Some(adapter);
+        }
+
+        let supported = match env::promise_result(0) {
+            PromiseResult::Successful(value) => Contract::payout_from_slice(price, &value).is_some(),
+            _ => false,
+        };
+        if !supported {
+            return None;
+        }
+
+        adapter.payouts = PayoutSupport::Supported;
+        self.nft_adapters.insert(nft_contract_id, &adapter);
+        MarketEvent::NftAdapterChange(vec![NftAdapterEvent {
+            nft_contract_id: nft_contract_id.clone(),
+            adapter: Some(adapter.clone()),
+            learned: true,
+        }]).emit();
+
+        Some(adapter)
+    }
+
+    // the listing stays and can be bought again, once an admin sets the adapter if need be
+    fn fail_probe(&mut self, nft_uid: TokenUID, nft_data: TokenData, buyer_id: AccountId, price: U128) {
+        // the buyer of an auction is its highest bidder
+        if let Some(mut auction) = self.auctions.get(&nft_uid) {
+            if auction.highest_bid.take().is_some() {
+                self.auctions.insert(&nft_uid, &auction);
+            }
+        }
+        Contract::transfer_funds(&nft_data.ft_token_id, buyer_id.clone(), price.0);
+        self.uid_to_data.insert(&nft_uid, &TokenData {
+            pending_sale: None,
+            ..nft_data.clone()
+        });
+
+        MarketEvent::PurchaseFailed(vec![PurchaseFailedEvent {
+            nft_uid,
+            buyer_id,
+            seller_id: nft_data.owner_id,
+            price,
+            ft_token_id: nft_data.ft_token_id,
+        }]).emit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::mock::VmAction;
+    use near_sdk::serde_json::json;
+    use near_sdk::test_utils::{accounts, get_created_receipts, testing_env_with_promise_results, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const PRICE: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn context(predecessor_id: AccountId, attached_deposit: Balance) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .predecessor_account_id(predecessor_id.clone())
+            .signer_account_id(predecessor_id)
+            .attached_deposit(attached_deposit);
+        builder
+    }
+
+    // `accounts(2)` buys token "1" of the NFT contract `accounts(3)` listed by `accounts(1)`
+    fn setup() -> Contract {
+        testing_env!(context(accounts(0), 0).build());
+        let mut contract = Contract::new(accounts(0));
+        testing_env!(context(accounts(1), PRICE).build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context(accounts(1), 0).predecessor_account_id(accounts(3)).build());
+        let msg = json!({ "json_nft": { "title": "NFT", "copies": "1", "price": PRICE.to_string() } });
+        contract.nft_on_approve("1".to_string(), accounts(1), 0, msg.to_string());
+        contract
+    }
+
+    fn uid() -> TokenUID {
+        format!("{}{}{}", accounts(3), UID_DELIMITER, "1")
+    }
+
+    fn buy(contract: &mut Contract) {
+        testing_env!(context(accounts(2), PRICE).build());
+        contract.buy(accounts(3), "1".to_string(), None, None, None);
+    }
+
+    fn resolve_probe(contract: &mut Contract, result: PromiseResult) {
+        testing_env_with_promise_results(context(accounts(0), 0).build(), result);
+        contract.resolve_payout_probe(accounts(2), uid(), U128::from(PRICE),
+                                      U64::from(GAS_FOR_NFT_TRANSFER.0));
+    }
+
+    // methods called on the NFT contract by the last call
+    fn nft_calls() -> Vec<String> {
+        get_created_receipts().into_iter()
+            .filter(|receipt| receipt.receiver_id == accounts(3))
+            .flat_map(|receipt| receipt.actions)
+            .filter_map(|action| match action {
+                VmAction::FunctionCall { method_name, .. } => Some(method_name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unknown_contract_is_probed_first() {
+        let mut contract = setup();
+        buy(&mut contract);
+        assert_eq!(nft_calls(), vec!["nft_payout".to_string()]);
+    }
+
+    #[test]
+    fn valid_payout_is_learned() {
+        let mut contract = setup();
+        buy(&mut contract);
+        let payout = json!({ "payout": { accounts(1).to_string(): PRICE.to_string() } });
+        resolve_probe(&mut contract, PromiseResult::Successful(payout.to_string().into_bytes()));
+
+        assert_eq!(contract.nft_adapter(&accounts(3)).payouts, PayoutSupport::Supported);
+        assert_eq!(nft_calls(), vec!["nft_transfer_payout".to_string()]);
+    }
+
+    #[test]
+    fn failed_probe_refunds_the_buyer() {
+        let mut contract = setup();
+        buy(&mut contract);
+        resolve_probe(&mut contract, PromiseResult::Failed);
+
+        // nothing is learned, the listing stays and can be bought again
+        assert_eq!(contract.nft_adapter(&accounts(3)).payouts, PayoutSupport::Unknown);
+        assert!(nft_calls().is_empty());
+        let nft_data = contract.uid_to_data.get(&uid()).unwrap();
+        assert!(nft_data.pending_sale.is_none());
+        let refund = get_created_receipts().into_iter()
+            .filter(|receipt| receipt.receiver_id == accounts(2))
+            .flat_map(|receipt| receipt.actions)
+            .any(|action| action == VmAction::Transfer { deposit: PRICE });
+        assert!(refund);
+    }
+
+    #[test]
+    fn unsupported_contract_is_sent_without_payouts() {
+        let mut contract = setup();
+        testing_env!(context(accounts(0), 0).build());
+        contract.set_nft_adapter(accounts(3), PayoutSupport::Unsupported, None, None);
+
+        buy(&mut contract);
+        assert_eq!(nft_calls(), vec!["nft_transfer".to_string()]);
+    }
+}
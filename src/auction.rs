@@ -0,0 +1,306 @@
+use crate::*;
+
+// bids placed this close to the end push it back by the same amount
+const AUCTION_EXTENSION: u64 = 10 * 60 * 1_000_000_000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionArgs {
+    pub reserve_price: U128,
+    /// Nanoseconds, the auction starts right away if not set.
+    pub start_at: Option<U64>,
+    pub end_at: U64,
+    pub min_increment: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bid {
+    pub bidder_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Auction {
+    pub reserve_price: U128,
+    pub start_at: U64,
+    pub end_at: U64,
+    pub min_increment: U128,
+    pub highest_bid: Option<Bid>,
+}
+
+impl Auction {
+    pub fn new(args: &AuctionArgs) -> Self {
+        let start_at = args.start_at.map(|x| x.0).unwrap_or_else(env::block_timestamp);
+        assert!(args.end_at.0 > start_at, "Auction must end after it starts");
+        assert!(args.end_at.0 > env::block_timestamp(), "Auction end is in the past");
+        assert!(args.min_increment.0 > 0, "Min increment must be positive");
+
+        Self {
+            reserve_price: args.reserve_price,
+            start_at: U64::from(start_at),
+            end_at: args.end_at,
+            min_increment: args.min_increment,
+            highest_bid: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuctionArgs {
+    pub start_price: U128,
+    pub end_price: U128,
+    /// Nanoseconds, the price starts to decline right away if not set.
+    pub start_at: Option<U64>,
+    pub end_at: U64,
+    /// Price drops once per step (nanoseconds) instead of continuously if set.
+    pub step: Option<U64>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuction {
+    pub start_price: U128,
+    pub end_price: U128,
+    pub start_at: U64,
+    pub end_at: U64,
+    pub step: Option<U64>,
+}
+
+impl DutchAuction {
+    pub fn new(args: &DutchAuctionArgs) -> Self {
+        let start_at = args.start_at.map(|x| x.0).unwrap_or_else(env::block_timestamp);
+        assert!(args.end_at.0 > start_at, "Dutch auction must end after it starts");
+        assert!(args.start_price.0 > args.end_price.0, "Start price must be above the end price");
+        if let Some(step) = args.step {
+            assert!(step.0 > 0, "Price step must be positive");
+        }
+
+        Self {
+            start_price: args.start_price,
+            end_price: args.end_price,
+            start_at: U64::from(start_at),
+            end_at: args.end_at,
+            step: args.step,
+        }
+    }
+
+    pub fn price_at(&self, timestamp: u64) -> u128 {
+        if timestamp <= self.start_at.0 {
+            return self.start_price.0;
+        }
+        if timestamp >= self.end_at.0 {
+            return self.end_price.0;
+        }
+
+        let mut elapsed = (timestamp - self.start_at.0) as u128;
+        if let Some(step) = self.step {
+            elapsed -= elapsed % step.0 as u128;
+        }
+        let duration = (self.end_at.0 - self.start_at.0) as u128;
+        let diff = self.start_price.0 - self.end_price.0;
+
+        self.start_price.0 - mul_div(diff, elapsed, duration)
+    }
+}
+
+impl Contract {
+    pub(crate) fn current_price(&self, nft_uid: &TokenUID, nft_data: &TokenData) -> u128 {
+        if let Some(dutch_auction) = self.dutch_auctions.get(nft_uid) {
+            dutch_auction.price_at(env::block_timestamp())
+        } else {
+            nft_data.price
+        }
+    }
+
+    pub(crate) fn with_current_price(&self, nft_uid: &TokenUID, nft_data: TokenData) -> TokenData {
+        TokenData {
+            price: self.current_price(nft_uid, &nft_data),
+            ..nft_data
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn place_bid(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    ) {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        let nft_data = self.uid_to_data.get(&nft_uid.clone())
+            .expect("NFT does not exist.");
+        let mut auction = self.auctions.get(&nft_uid.clone())
+            .expect("NFT is not on auction.");
+
+        let now = env::block_timestamp();
+        let bidder_id = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+
+        assert!(now >= auction.start_at.0, "Auction has not started yet");
+        assert!(now < auction.end_at.0, "Auction is over");
+        self.assert_not_paused(PauseScope::Purchases, &nft_contract_id);
+        assert_ne!(nft_data.owner_id, bidder_id);
+
+        let min_bid = if let Some(bid) = &auction.highest_bid {
+            bid.amount.0 + auction.min_increment.0
+        } else {
+            auction.reserve_price.0
+        };
+        assert!(amount > 0, "Bid requires a deposit");
+        assert!(amount >= min_bid, "Bid must be at least {}", min_bid);
+
+        // outbid, return the escrowed deposit
+        if let Some(bid) = auction.highest_bid.take() {
+            Promise::new(bid.bidder_id).transfer(bid.amount.0);
+        }
+
+        auction.highest_bid = Some(Bid {
+            bidder_id: bidder_id.clone(),
+            amount: U128::from(amount),
+        });
+        if auction.end_at.0 - now < AUCTION_EXTENSION {
+            auction.end_at = U64::from(now + AUCTION_EXTENSION);
+        }
+        // the bid is paid by the market, the seller is only ever charged for the listing
+        self.auctions.insert(&nft_uid.clone(), &auction);
+
+        self.uid_to_data.insert(&nft_uid.clone(), &TokenData {
+            price: amount,
+            ..nft_data
+        });
+
+        MarketEvent::PlaceBid(vec![PlaceBidEvent {
+            nft_contract_id,
+            token_id,
+            bidder_id,
+            amount: U128::from(amount),
+            end_at: auction.end_at,
+        }]).emit();
+    }
+
+    pub fn settle_auction(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    ) {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        let nft_data = self.uid_to_data.get(&nft_uid.clone())
+            .expect("NFT does not exist.");
+        let auction = self.auctions.get(&nft_uid.clone())
+            .expect("NFT is not on auction.");
+
+        assert!(env::block_timestamp() >= auction.end_at.0, "Auction is not over yet");
+        assert!(nft_data.pending_sale.is_none(), "Auction is already being settled");
+        if auction.highest_bid.is_some() {
+            self.assert_not_paused(PauseScope::Purchases, &nft_contract_id);
+        }
+
+        MarketEvent::SettleAuction(vec![SettleAuctionEvent {
+            nft_contract_id,
+            token_id,
+            owner_id: nft_data.owner_id.clone(),
+            winning_bid: auction.highest_bid.clone(),
+        }]).emit();
+
+        if let Some(bid) = auction.highest_bid {
+            self.transfer_nft(nft_data, bid.bidder_id, bid.amount);
+        } else {
+            // nobody met the reserve price
+            self.remove_nft(nft_data.owner_id, nft_uid);
+        }
+    }
+
+    /// Returns the winning bid of an auction that can't be settled because purchases are paused,
+    /// callable by its bidder. The listing is kept and ends without a winner.
+    pub fn reclaim_bid(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    ) {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        let mut auction = self.auctions.get(&nft_uid.clone())
+            .expect("NFT is not on auction.");
+        let bid = auction.highest_bid.clone()
+            .expect("Auction has no bids");
+
+        assert_eq!(env::predecessor_account_id(), bid.bidder_id, "You are not the highest bidder");
+        assert!(env::block_timestamp() >= auction.end_at.0, "Auction is not over yet");
+        assert!(self.is_paused(PauseScope::Purchases, nft_contract_id.clone()),
+                "Purchases are not paused, settle the auction instead");
+
+        auction.highest_bid = None;
+        self.auctions.insert(&nft_uid, &auction);
+        Promise::new(bid.bidder_id.clone()).transfer(bid.amount.0);
+
+        MarketEvent::ReclaimBid(vec![PlaceBidEvent {
+            nft_contract_id,
+            token_id,
+            bidder_id: bid.bidder_id,
+            amount: bid.amount,
+            end_at: auction.end_at,
+        }]).emit();
+    }
+
+    pub fn get_auction(self, nft_contract_id: AccountId, token_id: TokenId) -> Option<Auction> {
+        self.auctions.get(&format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id))
+    }
+
+    pub fn get_dutch_auction(self, nft_contract_id: AccountId, token_id: TokenId) -> Option<DutchAuction> {
+        self.dutch_auctions.get(&format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dutch_auction(start_price: u128, end_price: u128, step: Option<u64>) -> DutchAuction {
+        DutchAuction {
+            start_price: U128::from(start_price),
+            end_price: U128::from(end_price),
+            start_at: U64::from(1_000),
+            end_at: U64::from(2_000),
+            step: step.map(U64::from),
+        }
+    }
+
+    #[test]
+    fn price_is_capped_outside_the_auction() {
+        let auction = dutch_auction(300, 100, None);
+        assert_eq!(auction.price_at(0), 300);
+        assert_eq!(auction.price_at(1_000), 300);
+        assert_eq!(auction.price_at(2_000), 100);
+        assert_eq!(auction.price_at(5_000), 100);
+    }
+
+    #[test]
+    fn price_declines_linearly_and_rounds_up() {
+        let auction = dutch_auction(300, 100, None);
+        assert_eq!(auction.price_at(1_500), 200);
+        // the drop of 0.2 is rounded down, the price stays at the start
+        assert_eq!(auction.price_at(1_001), 300);
+        assert_eq!(auction.price_at(1_999), 101);
+    }
+
+    #[test]
+    fn price_drops_once_per_step() {
+        let auction = dutch_auction(300, 100, Some(300));
+        assert_eq!(auction.price_at(1_299), 300);
+        assert_eq!(auction.price_at(1_300), 240);
+        assert_eq!(auction.price_at(1_599), 240);
+        assert_eq!(auction.price_at(1_900), 120);
+    }
+
+    #[test]
+    fn large_prices_do_not_overflow() {
+        let auction = dutch_auction(u128::MAX, 0, None);
+        assert_eq!(auction.price_at(1_500), u128::MAX - u128::MAX / 2);
+        // a thousandth of the start price is left, rounded up as the drop is rounded down
+        assert_eq!(auction.price_at(1_999), u128::MAX / 1_000 + 1);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::*;
+
+// every item costs an NFT transfer and a `resolve_purchase`, the attached gas is checked for all of them
+const MAX_CART_SIZE: usize = 3;
+const GAS_FOR_BUY_MANY_SUMMARY: Gas = Gas(BASE_GAS.0 * 2u64);
+// listings looked at by a sweep before giving up on filling it
+const MAX_SWEEP_SCAN: usize = 50;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CartItem {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub max_price: Option<U128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Buys every available item with the attached deposit, the rest of it is refunded.
+    #[payable]
+    pub fn buy_many(&mut self, items: Vec<CartItem>) {
+        assert!(!items.is_empty() && items.len() <= MAX_CART_SIZE,
+                "Cart must have from 1 to {} items", MAX_CART_SIZE);
+
+        let buyer_id = env::predecessor_account_id();
+        let mut purchases: Vec<(TokenUID, TokenData, u128)> = vec![];
+        let mut skipped: Vec<TokenUID> = vec![];
+
+        for item in items {
+            let nft_uid: TokenUID = format!("{}{}{}", item.nft_contract_id, UID_DELIMITER, item.token_id);
+            let nft_data = self.uid_to_data.get(&nft_uid.clone())
+                .filter(|x| x.ft_token_id.is_none())
+                .filter(|_| purchases.iter().all(|(uid, _, _)| uid != &nft_uid));
+
+            let price = nft_data.as_ref().and_then(|x| {
+                self.purchase_price(&nft_uid, x, &buyer_id, item.max_price, None).ok()
+            });
+
+            // sold, unlisted or repriced items are not bought and not charged
+            match (nft_data, price) {
+                (Some(nft_data), Some(price)) => purchases.push((nft_uid, nft_data, price)),
+                _ => skipped.push(nft_uid),
+            }
+        }
+
+        self.buy_listed(buyer_id, purchases, skipped);
+    }
+
+    /// Buys up to `count` cheapest listings of the collection within the `max_total` budget,
+    /// the attached deposit above the spent amount is refunded.
+    #[payable]
+    pub fn sweep_collection(
+        &mut self,
+        nft_contract_id: AccountId,
+        count: u32,
+        max_total: U128,
+        max_unit_price: Option<U128>,
+    ) {
+        assert!(count > 0 && count as usize <= MAX_CART_SIZE,
+                "Sweep must buy from 1 to {} items", MAX_CART_SIZE);
+        assert!(env::attached_deposit() >= max_total.0, "Attached deposit is less than max_total");
+
+        let buyer_id = env::predecessor_account_id();
+        let mut purchases: Vec<(TokenUID, TokenData, u128)> = vec![];
+        let mut total: u128 = 0;
+
+        if let Some(prices) = self.prices_by_contract.get(&nft_contract_id) {
+            for ((price, nft_uid), _) in prices.iter().take(MAX_SWEEP_SCAN) {
+                if purchases.len() == count as usize
+                    || max_unit_price.is_some_and(|x| price > x.0)
+                    || total + price > max_total.0 {
+                    break;
+                }
+
+                let nft_data = self.uid_to_data.get(&nft_uid.clone()).unwrap();
+                // expired, pending or own listings are passed over
+                if self.purchase_price(&nft_uid, &nft_data, &buyer_id, None, None).is_ok() {
+                    total += price;
+                    purchases.push((nft_uid, nft_data, price));
+                }
+            }
+        }
+
+        self.buy_listed(buyer_id, purchases, vec![]);
+    }
+
+    #[private]
+    pub fn resolve_buy_many(
+        &mut self,
+        buyer_id: AccountId,
+        purchased: Vec<TokenUID>,
+        skipped: Vec<TokenUID>,
+    ) {
+        let mut bought: Vec<TokenUID> = vec![];
+        let mut failed: Vec<TokenUID> = vec![];
+
+        for (i, nft_uid) in purchased.into_iter().enumerate() {
+            let is_bought = match env::promise_result(i as u64) {
+                PromiseResult::Successful(value) => {
+                    near_sdk::serde_json::from_slice::<bool>(&value).unwrap_or(false)
+                }
+                _ => false
+            };
+            // failed items were refunded by their `resolve_purchase`
+            if is_bought {
+                bought.push(nft_uid);
+            } else {
+                failed.push(nft_uid);
+            }
+        }
+
+        MarketEvent::BuyMany(vec![BuyManyEvent {
+            buyer_id,
+            bought,
+            failed,
+            skipped,
+        }]).emit();
+    }
+}
+
+impl Contract {
+    // buys listings checked by the caller in one batch, refunding whatever the deposit exceeds
+    pub(crate) fn buy_listed(
+        &mut self,
+        buyer_id: AccountId,
+        purchases: Vec<(TokenUID, TokenData, u128)>,
+        skipped: Vec<TokenUID>,
+    ) {
+        let deposit = env::attached_deposit();
+        let total: u128 = purchases.iter().map(|(_, _, price)| price).sum();
+
+        assert!(deposit >= total, "Attached deposit is less than the total price {}", total);
+        if deposit > total {
+            Promise::new(buyer_id.clone()).transfer(deposit - total);
+        }
+
+        if purchases.is_empty() {
+            MarketEvent::BuyMany(vec![BuyManyEvent {
+                buyer_id,
+                bought: vec![],
+                failed: vec![],
+                skipped,
+            }]).emit();
+            return;
+        }
+
+        // each sale needs the gas of its NFT contract, checked before any transfer is made
+        let gas_for_sales: u64 = purchases.iter()
+            .map(|(_, nft_data, _)| {
+                let adapter = self.nft_adapter(&nft_data.nft_contract_id);
+                Contract::gas_for_sale(&adapter, &nft_data.ft_token_id).0 + BASE_GAS.0
+            })
+            .sum();
+        let gas_required = gas_for_sales + GAS_FOR_BUY_MANY_SUMMARY.0;
+        assert!(env::prepaid_gas().0 - env::used_gas().0 >= gas_required,
+                "Not enough gas attached, the cart needs {} more", gas_required);
+
+        let mut purchased: Vec<TokenUID> = vec![];
+        let mut transfers: Option<Promise> = None;
+        for (nft_uid, nft_data, price) in purchases {
+            let transfer = self.transfer_nft(nft_data, buyer_id.clone(), U128::from(price));
+            transfers = Some(match transfers {
+                Some(prev) => prev.and(transfer),
+                None => transfer,
+            });
+            purchased.push(nft_uid);
+        }
+
+        transfers.unwrap().then(ext_self::resolve_buy_many(
+            buyer_id,
+            purchased,
+            skipped,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_BUY_MANY_SUMMARY,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::mock::VmAction;
+    use near_sdk::serde_json::json;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const PRICE: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn context(predecessor_id: AccountId, attached_deposit: Balance) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .predecessor_account_id(predecessor_id.clone())
+            .signer_account_id(predecessor_id)
+            .attached_deposit(attached_deposit);
+        builder
+    }
+
+    // `accounts(1)` lists token "1" of the NFT contract `accounts(3)`
+    fn setup() -> Contract {
+        testing_env!(context(accounts(0), 0).build());
+        let mut contract = Contract::new(accounts(0));
+        testing_env!(context(accounts(1), PRICE).build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context(accounts(1), 0).predecessor_account_id(accounts(3)).build());
+        let msg = json!({ "json_nft": { "title": "NFT", "copies": "1", "price": PRICE.to_string() } });
+        contract.nft_on_approve("1".to_string(), accounts(1), 0, msg.to_string());
+        contract
+    }
+
+    fn item(token_id: &str, max_price: Option<Balance>) -> CartItem {
+        CartItem {
+            nft_contract_id: accounts(3),
+            token_id: token_id.to_string(),
+            max_price: max_price.map(U128::from),
+        }
+    }
+
+    fn refunded(buyer_id: &AccountId) -> Balance {
+        get_created_receipts().into_iter()
+            .filter(|receipt| &receipt.receiver_id == buyer_id)
+            .flat_map(|receipt| receipt.actions)
+            .map(|action| match action {
+                VmAction::Transfer { deposit } => deposit,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn unavailable_items_are_refunded() {
+        let mut contract = setup();
+        testing_env!(context(accounts(2), PRICE * 3).build());
+        // not listed and listed twice, only the first one is bought
+        contract.buy_many(vec![item("1", None), item("2", None), item("1", None)]);
+
+        let nft_uid = format!("{}{}{}", accounts(3), UID_DELIMITER, "1");
+        let pending_sale = contract.uid_to_data.get(&nft_uid).unwrap().pending_sale.unwrap();
+        assert_eq!(pending_sale.buyer_id, accounts(2));
+        assert_eq!(refunded(&accounts(2)), PRICE * 2);
+    }
+
+    #[test]
+    fn items_above_max_price_are_refunded() {
+        let mut contract = setup();
+        testing_env!(context(accounts(2), PRICE).build());
+        contract.buy_many(vec![item("1", Some(PRICE - 1))]);
+
+        let nft_uid = format!("{}{}{}", accounts(3), UID_DELIMITER, "1");
+        assert!(contract.uid_to_data.get(&nft_uid).unwrap().pending_sale.is_none());
+        assert_eq!(refunded(&accounts(2)), PRICE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit is less than the total price")]
+    fn deposit_must_cover_the_available_items() {
+        let mut contract = setup();
+        testing_env!(context(accounts(2), PRICE - 1).build());
+        contract.buy_many(vec![item("1", None), item("2", None)]);
+    }
+}
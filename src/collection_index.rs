@@ -0,0 +1,89 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Listings of one NFT contract, newest first like `get_nfts`.
+    pub fn get_nfts_by_contract(self, nft_contract_id: AccountId, from: u64, limit: u64) -> MarketData {
+        let uids = if let Some(uids) = self.uids_by_contract.get(&nft_contract_id) {
+            uids
+        } else {
+            return MarketData {
+                tokens: vec![],
+                has_next_batch: false,
+                total_count: 0,
+            };
+        };
+
+        let size = uids.len();
+        let mut res = vec![];
+        if from >= size {
+            return MarketData {
+                tokens: res,
+                has_next_batch: false,
+                total_count: size,
+            };
+        }
+        let real_to = size - from;
+        let real_from = real_to.saturating_sub(limit);
+
+        for i in (real_from..real_to).rev() {
+            let uid = uids.as_vector().get(i).unwrap();
+            let data = self.uid_to_data.get(&uid).unwrap();
+            if !data.is_expired() {
+                res.push(self.with_current_price(&uid, data))
+            }
+        }
+
+        MarketData {
+            tokens: res,
+            has_next_batch: real_from > 0,
+            total_count: size,
+        }
+    }
+
+    pub fn get_listing_count_by_contract(self, nft_contract_id: AccountId) -> u64 {
+        self.uids_by_contract.get(&nft_contract_id).map_or(0, |x| x.len())
+    }
+}
+
+impl Contract {
+    pub(crate) fn index_by_contract(&mut self, nft_uid: &TokenUID, nft_contract_id: &AccountId, owner_id: &AccountId) {
+        let mut uids = self.uids_by_contract
+            .get(nft_contract_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::TokenUIDsByContractInner {
+                    account_id_hash: hash_account_id(nft_contract_id)
+                })
+            });
+        if uids.insert(nft_uid) {
+            self.uids_by_contract.insert(nft_contract_id, &uids);
+        }
+
+        let key = (owner_id.clone(), nft_contract_id.clone());
+        let mut owner_uids = self.uids_by_owner_contract
+            .get(&key)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::TokenUIDsByOwnerContractInner {
+                    owner_contract_hash: hash_token_uid(&format!("{}{}{}", owner_id, UID_DELIMITER, nft_contract_id))
+                })
+            });
+        if owner_uids.insert(nft_uid) {
+            self.uids_by_owner_contract.insert(&key, &owner_uids);
+        }
+    }
+
+    pub(crate) fn unindex_by_contract(&mut self, nft_uid: &TokenUID, nft_contract_id: &AccountId, owner_id: &AccountId) {
+        if let Some(mut uids) = self.uids_by_contract.get(nft_contract_id) {
+            if uids.remove(nft_uid) {
+                self.uids_by_contract.insert(nft_contract_id, &uids);
+            }
+        }
+
+        let key = (owner_id.clone(), nft_contract_id.clone());
+        if let Some(mut owner_uids) = self.uids_by_owner_contract.get(&key) {
+            if owner_uids.remove(nft_uid) {
+                self.uids_by_owner_contract.insert(&key, &owner_uids);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VerifiedCollection {
+    pub contract_id: AccountId,
+    pub contract_name: String,
+    pub icon: Option<String>,
+    pub website: Option<String>,
+    // may register the royalties of the collection, see `set_collection_royalties`
+    pub creator_id: Option<AccountId>,
+    pub verified_at: U64,
+    pub verified_by: AccountId,
+    // revoked collections stay in the registry so their history can be looked up
+    pub revoked_at: Option<U64>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Adds `contract_id` to the registry or verifies it again with new details.
+    #[payable]
+    pub fn verify_contract(
+        &mut self,
+        contract_id: AccountId,
+        contract_name: String,
+        icon: Option<String>,
+        website: Option<String>,
+        creator_id: Option<AccountId>,
+    ) {
+        self.assert_role(Role::Moderator);
+        let collection = VerifiedCollection {
+            contract_id: contract_id.clone(),
+            contract_name,
+            icon,
+            website,
+            creator_id,
+            verified_at: U64::from(env::block_timestamp()),
+            verified_by: env::predecessor_account_id(),
+            revoked_at: None,
+        };
+        self.verified_collections.insert(&contract_id, &collection);
+
+        MarketEvent::VerifyContract(vec![collection]).emit();
+    }
+
+    pub fn revoke_verification(&mut self, contract_id: AccountId) {
+        self.assert_role(Role::Moderator);
+        let mut collection = self.verified_collections.get(&contract_id)
+            .filter(|x| x.revoked_at.is_none())
+            .expect("Contract is not verified.");
+        collection.revoked_at = Some(U64::from(env::block_timestamp()));
+        self.verified_collections.insert(&contract_id, &collection);
+
+        MarketEvent::RevokeVerification(vec![collection]).emit();
+    }
+
+    /// In verified-only mode NFTs of unverified contracts can't be listed, existing listings stay.
+    pub fn set_verified_only(&mut self, enabled: bool) {
+        self.assert_role(Role::Moderator);
+        self.verified_only = enabled;
+
+        MarketEvent::VerifiedOnlyChange(vec![VerifiedOnlyEvent { enabled }]).emit();
+    }
+
+    pub fn is_verified(&self, contract_id: AccountId) -> bool {
+        self.verified_collections.get(&contract_id)
+            .is_some_and(|x| x.revoked_at.is_none())
+    }
+
+    pub fn is_verified_only(&self) -> bool {
+        self.verified_only
+    }
+
+    pub fn get_verified_collection(&self, contract_id: AccountId) -> Option<VerifiedCollection> {
+        self.verified_collections.get(&contract_id)
+    }
+
+    /// Registry entries in insertion order, revoked ones included.
+    pub fn get_verified_collections(&self, from: u64, limit: u64) -> Vec<VerifiedCollection> {
+        let collections = self.verified_collections.values_as_vector();
+        (from..min(from.saturating_add(limit), collections.len()))
+            .map(|i| collections.get(i).unwrap())
+            .collect()
+    }
+
+    pub fn get_verified_collections_count(&self) -> u64 {
+        self.verified_collections.len()
+    }
+}
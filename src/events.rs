@@ -0,0 +1,268 @@
+use crate::*;
+
+const EVENT_STANDARD: &str = "mjol_market";
+const EVENT_VERSION: &str = "1.0.0";
+
+/// NEP-297 event, logged as `EVENT_JSON:{"standard", "version", "event", "data"}`.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum MarketEvent {
+    NftOnApprove(Vec<ListingEvent>),
+    ListingReplaced(Vec<ListingReplacedEvent>),
+    UpdateTokenPrice(Vec<PriceUpdateEvent>),
+    RemoveFromMarket(Vec<ListingRemovedEvent>),
+    RemoveOldListing(Vec<ListingRemovedEvent>),
+    CleanupExpired(Vec<ListingExpiredEvent>),
+    RefreshListing(Vec<ListingRefreshedEvent>),
+    VerifyContract(Vec<VerifiedCollection>),
+    RevokeVerification(Vec<VerifiedCollection>),
+    VerifiedOnlyChange(Vec<VerifiedOnlyEvent>),
+    ResolvePurchase(Vec<PurchaseEvent>),
+    PurchaseFailed(Vec<PurchaseFailedEvent>),
+    BuyMany(Vec<BuyManyEvent>),
+    PlaceBid(Vec<PlaceBidEvent>),
+    SettleAuction(Vec<SettleAuctionEvent>),
+    ReclaimBid(Vec<PlaceBidEvent>),
+    MakeOffer(Vec<Offer>),
+    CancelOffer(Vec<Offer>),
+    AcceptOffer(Vec<AcceptOfferEvent>),
+    MakeCollectionOffer(Vec<CollectionOffer>),
+    CancelCollectionOffer(Vec<CollectionOffer>),
+    AcceptCollectionOffer(Vec<AcceptCollectionOfferEvent>),
+    ResolveOfferFailed(Vec<PurchaseFailedEvent>),
+    DefaultFeeChange(Vec<DefaultFeeChangeEvent>),
+    TreasuryChange(Vec<TreasuryChangeEvent>),
+    CollectionFeeChange(Vec<CollectionFeeChangeEvent>),
+    RoyaltiesChange(Vec<RoyaltiesChangeEvent>),
+    MaxRoyaltyChange(Vec<MaxRoyaltyChangeEvent>),
+    NftAdapterChange(Vec<NftAdapterEvent>),
+    FeePromotionAdded(Vec<FeePromotion>),
+    FeePromotionRemoved(Vec<FeePromotion>),
+    OwnershipTransferStarted(Vec<OwnershipEvent>),
+    OwnershipTransferred(Vec<OwnershipEvent>),
+    RoleGranted(Vec<RoleEvent>),
+    RoleRevoked(Vec<RoleEvent>),
+    Paused(Vec<Pause>),
+    Unpaused(Vec<Pause>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: MarketEvent,
+}
+
+impl MarketEvent {
+    pub fn emit(self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event: self,
+        };
+        env::log_str(&format!("EVENT_JSON:{}", to_string(&log).unwrap()));
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingEvent {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub approval_id: U64,
+    pub price: U128,
+    pub ft_token_id: Option<AccountId>,
+    pub json_nft: ApprovedNFTFull,
+    pub auction: Option<AuctionArgs>,
+    pub dutch_auction: Option<DutchAuctionArgs>,
+    pub expires_at: Option<U64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingReplacedEvent {
+    pub old_owner_id: AccountId,
+    pub old_approval_id: U64,
+    pub old_price: U128,
+    pub listing: ListingEvent,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceUpdateEvent {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub price: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingRemovedEvent {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub approval_id: U64,
+    pub price: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingExpiredEvent {
+    pub nft_uid: TokenUID,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingRefreshedEvent {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub approval_id: U64,
+    pub status: RefreshStatus,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VerifiedOnlyEvent {
+    pub enabled: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseEvent {
+    pub nft_uid: TokenUID,
+    pub buyer_id: AccountId,
+    pub seller_id: AccountId,
+    pub price: U128,
+    pub ft_token_id: Option<AccountId>,
+    // amounts received by the seller and royalty holders, after the treasury fee
+    pub payout: Payout,
+    pub treasury_fee: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseFailedEvent {
+    pub nft_uid: TokenUID,
+    pub buyer_id: AccountId,
+    pub seller_id: AccountId,
+    pub price: U128,
+    pub ft_token_id: Option<AccountId>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuyManyEvent {
+    pub buyer_id: AccountId,
+    pub bought: Vec<TokenUID>,
+    pub failed: Vec<TokenUID>,
+    pub skipped: Vec<TokenUID>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PlaceBidEvent {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub bidder_id: AccountId,
+    pub amount: U128,
+    pub end_at: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SettleAuctionEvent {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub winning_bid: Option<Bid>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptOfferEvent {
+    pub offer_id: U64,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub approval_id: U64,
+    pub buyer_id: AccountId,
+    pub price: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptCollectionOfferEvent {
+    pub offer_id: U64,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub approval_id: U64,
+    pub buyer_id: AccountId,
+    pub price: U128,
+    pub quantity_left: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DefaultFeeChangeEvent {
+    pub fee_bps: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryChangeEvent {
+    pub treasury_id: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionFeeChangeEvent {
+    pub nft_contract_id: AccountId,
+    // `None` when the collection is back on the default fee
+    pub fee_bps: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoyaltiesChangeEvent {
+    pub nft_contract_id: AccountId,
+    // `None` when the royalties were removed
+    pub royalties: Option<HashMap<AccountId, u32>>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MaxRoyaltyChangeEvent {
+    pub max_royalty_bps: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftAdapterEvent {
+    pub nft_contract_id: AccountId,
+    // `None` when the adapter was removed
+    pub adapter: Option<NftAdapter>,
+    // learned from the NFT contract rather than set by an admin
+    pub learned: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnershipEvent {
+    pub owner_id: AccountId,
+    pub new_owner_id: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleEvent {
+    pub account_id: AccountId,
+    pub role: Role,
+}
@@ -0,0 +1,160 @@
+use crate::*;
+
+// 0.1, a higher fee could eat into royalties paid by `nft_transfer_payout`
+const MAX_FEE_BPS: u32 = 1_000;
+// `effective_fee_bps` reads all of them in every purchase callback
+const MAX_FEE_PROMOTIONS: u64 = 20;
+
+/// `value * n / d` rounded down, split so `value * n` can't overflow on large prices.
+pub(crate) fn mul_div(value: u128, n: u128, d: u128) -> u128 {
+    value / d * n + value % d * n / d
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeConfig {
+    pub default_fee_bps: u32,
+    pub treasury_id: AccountId,
+}
+
+/// Fee applied to sales between `start_at` and `end_at`, of one collection or all of them.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeePromotion {
+    pub promotion_id: U64,
+    pub fee_bps: u32,
+    pub start_at: U64,
+    pub end_at: U64,
+    pub nft_contract_id: Option<AccountId>,
+}
+
+impl FeePromotion {
+    pub fn is_active(&self, nft_contract_id: &AccountId) -> bool {
+        let now = env::block_timestamp();
+        now >= self.start_at.0 && now < self.end_at.0
+            && self.nft_contract_id.as_ref().is_none_or(|x| x == nft_contract_id)
+    }
+
+    pub fn is_over(&self) -> bool {
+        env::block_timestamp() >= self.end_at.0
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeConfigView {
+    pub default_fee_bps: u32,
+    pub treasury_id: AccountId,
+    pub collection_fees: Vec<(AccountId, u32)>,
+    pub promotions: Vec<FeePromotion>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_default_fee(&mut self, fee_bps: u32) {
+        self.assert_role(Role::FeeManager);
+        assert!(fee_bps <= MAX_FEE_BPS, "Fee can't exceed {} bps", MAX_FEE_BPS);
+        self.fee_config.default_fee_bps = fee_bps;
+
+        MarketEvent::DefaultFeeChange(vec![DefaultFeeChangeEvent { fee_bps }]).emit();
+    }
+
+    pub fn set_treasury(&mut self, treasury_id: AccountId) {
+        self.assert_role(Role::FeeManager);
+        self.fee_config.treasury_id = treasury_id.clone();
+
+        MarketEvent::TreasuryChange(vec![TreasuryChangeEvent { treasury_id }]).emit();
+    }
+
+    /// Overrides the default fee for a collection, `None` brings the default back.
+    pub fn set_collection_fee(&mut self, nft_contract_id: AccountId, fee_bps: Option<u32>) {
+        self.assert_role(Role::FeeManager);
+        if let Some(fee_bps) = fee_bps {
+            assert!(fee_bps <= MAX_FEE_BPS, "Fee can't exceed {} bps", MAX_FEE_BPS);
+            self.collection_fees.insert(&nft_contract_id, &fee_bps);
+        } else {
+            self.collection_fees.remove(&nft_contract_id);
+        }
+
+        MarketEvent::CollectionFeeChange(vec![CollectionFeeChangeEvent {
+            nft_contract_id,
+            fee_bps,
+        }]).emit();
+    }
+
+    pub fn add_fee_promotion(
+        &mut self,
+        fee_bps: u32,
+        start_at: U64,
+        end_at: U64,
+        nft_contract_id: Option<AccountId>,
+    ) -> U64 {
+        self.assert_role(Role::FeeManager);
+        assert!(fee_bps <= MAX_FEE_BPS, "Fee can't exceed {} bps", MAX_FEE_BPS);
+        assert!(end_at.0 > start_at.0, "Promotion must end after it starts");
+        assert!(end_at.0 > env::block_timestamp(), "Promotion is already over");
+
+        self.prune_fee_promotions();
+        assert!(self.fee_promotions.len() < MAX_FEE_PROMOTIONS,
+                "There can be at most {} promotions", MAX_FEE_PROMOTIONS);
+
+        let promotion = FeePromotion {
+            promotion_id: U64::from(self.next_fee_promotion_id),
+            fee_bps,
+            start_at,
+            end_at,
+            nft_contract_id,
+        };
+        self.next_fee_promotion_id += 1;
+        self.fee_promotions.insert(&promotion.promotion_id.0, &promotion);
+
+        let promotion_id = promotion.promotion_id;
+        MarketEvent::FeePromotionAdded(vec![promotion]).emit();
+
+        promotion_id
+    }
+
+    pub fn remove_fee_promotion(&mut self, promotion_id: U64) {
+        self.assert_role(Role::FeeManager);
+        let promotion = self.fee_promotions.remove(&promotion_id.0)
+            .expect("Promotion does not exist.");
+
+        MarketEvent::FeePromotionRemoved(vec![promotion]).emit();
+    }
+
+    pub fn get_fee_config(self) -> FeeConfigView {
+        FeeConfigView {
+            default_fee_bps: self.fee_config.default_fee_bps,
+            treasury_id: self.fee_config.treasury_id.clone(),
+            collection_fees: self.collection_fees.to_vec(),
+            promotions: self.fee_promotions.values().collect(),
+        }
+    }
+
+    pub fn get_effective_fee(self, nft_contract_id: AccountId) -> u32 {
+        self.effective_fee_bps(&nft_contract_id)
+    }
+}
+
+impl Contract {
+    /// Fee of a sale made now, the lowest active promotion wins over the regular fee.
+    pub(crate) fn effective_fee_bps(&self, nft_contract_id: &AccountId) -> u32 {
+        let fee_bps = self.collection_fees.get(nft_contract_id)
+            .unwrap_or(self.fee_config.default_fee_bps);
+
+        self.fee_promotions.values()
+            .filter(|x| x.is_active(nft_contract_id))
+            .map(|x| x.fee_bps)
+            .fold(fee_bps, min)
+    }
+
+    fn prune_fee_promotions(&mut self) {
+        let over: Vec<FeePromotion> = self.fee_promotions.values()
+            .filter(|x| x.is_over())
+            .collect();
+        for promotion in over {
+            self.fee_promotions.remove(&promotion.promotion_id.0);
+            MarketEvent::FeePromotionRemoved(vec![promotion]).emit();
+        }
+    }
+}
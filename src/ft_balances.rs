@@ -0,0 +1,34 @@
+use near_sdk::assert_one_yocto;
+
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Credits `amount` to `receiver_id` if the `ft_transfer` of a payout or refund failed,
+    /// most likely because the receiver isn't registered with the token.
+    #[private]
+    pub fn resolve_ft_transfer(&mut self, ft_token_id: AccountId, receiver_id: AccountId, amount: U128) {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return;
+        }
+
+        let key = (receiver_id, ft_token_id);
+        let balance = self.ft_balances.get(&key).unwrap_or(0);
+        self.ft_balances.insert(&key, &(balance + amount.0));
+    }
+
+    /// Sends the tokens credited to the caller, once it is registered with the token.
+    #[payable]
+    pub fn withdraw_ft(&mut self, ft_token_id: AccountId) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let amount = self.ft_balances.remove(&(account_id.clone(), ft_token_id.clone()))
+            .expect("Nothing to withdraw.");
+
+        Contract::transfer_funds(&Some(ft_token_id), account_id, amount);
+    }
+
+    pub fn get_ft_balance(self, account_id: AccountId, ft_token_id: AccountId) -> U128 {
+        U128::from(self.ft_balances.get(&(account_id, ft_token_id)).unwrap_or(0))
+    }
+}
@@ -2,14 +2,43 @@ use std::cmp::{max, min};
 use std::collections::HashMap;
 
 use near_contract_standards::non_fungible_token::{hash_account_id, TokenId};
-use near_sdk::{AccountId, Balance, BorshStorageKey, CryptoHash, env, Gas, near_bindgen, Promise, promise_result_as_success, PromiseResult, serde_json::json};
+use near_sdk::{AccountId, Balance, BorshStorageKey, CryptoHash, env, Gas, near_bindgen, Promise, promise_result_as_success, PromiseOrValue, PromiseResult};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{UnorderedMap, UnorderedSet, Vector};
+use near_sdk::collections::{TreeMap, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::ext_contract;
 use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::serde_json::to_string;
 
+pub use crate::adapters::*;
+pub use crate::auction::*;
+pub use crate::cart::*;
+pub use crate::collections::*;
+pub use crate::events::*;
+pub use crate::fees::*;
+pub use crate::liveness::*;
+pub use crate::offers::*;
+pub use crate::pause::*;
+pub use crate::roles::*;
+pub use crate::sales::*;
+
+mod adapters;
+mod auction;
+mod cart;
+mod collection_index;
+mod collections;
+mod events;
+mod fees;
+mod ft_balances;
+mod liveness;
+mod offers;
+mod pause;
+mod price_index;
+mod roles;
+mod royalties;
+mod sales;
+mod storage;
+
 #[ext_contract(nft_contract)]
 trait ExtContract {
     fn nft_transfer_payout(
@@ -28,6 +57,27 @@ trait ExtContract {
         approval_id: Option<u64>,
         memo: Option<String>,
     );
+
+    fn nft_token(&self, token_id: TokenId);
+
+    fn nft_payout(&self, token_id: TokenId, balance: U128, max_len_payout: u32);
+
+    fn nft_is_approved(
+        &self,
+        token_id: TokenId,
+        approved_account_id: AccountId,
+        approval_id: Option<u64>,
+    );
+}
+
+#[ext_contract(ft_contract)]
+trait ExtFungibleToken {
+    fn ft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+    );
 }
 
 #[ext_contract(ext_self)]
@@ -38,7 +88,8 @@ trait ExtSelf {
         seller_id: AccountId,
         nft_uid: TokenUID,
         price: U128,
-    );
+        ft_token_id: Option<AccountId>,
+    ) -> bool;
 
     fn resolve_purchase_no_payouts(
         &mut self,
@@ -46,19 +97,78 @@ trait ExtSelf {
         seller_id: AccountId,
         nft_uid: TokenUID,
         price: U128,
+        ft_token_id: Option<AccountId>,
+    ) -> bool;
+
+    fn resolve_offer(
+        &mut self,
+        buyer_id: AccountId,
+        seller_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
+    );
+
+    fn resolve_buy_many(
+        &mut self,
+        buyer_id: AccountId,
+        purchased: Vec<TokenUID>,
+        skipped: Vec<TokenUID>,
+    );
+
+    fn resolve_refresh_listing(
+        &mut self,
+        nft_uid: TokenUID,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> RefreshStatus;
+
+    fn resolve_checked_buy(
+        &mut self,
+        buyer_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
     );
+
+    fn resolve_clear_pending_sale(&mut self, nft_uid: TokenUID);
+
+    fn resolve_offer_probe(&mut self, sale: OfferSale, transfer_gas: U64);
+
+    fn resolve_ft_transfer(
+        &mut self,
+        ft_token_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    );
+
+    fn resolve_payout_probe(
+        &mut self,
+        buyer_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
+        transfer_gas: U64,
+    ) -> PromiseOrValue<()>;
 }
 
 const GAS_FOR_NFT_TRANSFER: Gas = Gas(20_000_000_000_000);
 const BASE_GAS: Gas = Gas(5_000_000_000_000);
 const GAS_FOR_ROYALTIES: Gas = Gas(BASE_GAS.0 * 10u64);
+const GAS_FOR_FT_TRANSFER: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_RESOLVE_FT_TRANSFER: Gas = Gas(BASE_GAS.0);
+// every payout receiver costs an extra `ft_transfer` when the listing is priced in a fungible token,
+// with its callback and the cost of sending both
+const GAS_FOR_FT_PAYOUT: Gas = Gas(GAS_FOR_FT_TRANSFER.0 + GAS_FOR_RESOLVE_FT_TRANSFER.0 + 3_000_000_000_000);
 const NO_DEPOSIT: Balance = 0;
 
-const TREASURY_FEE: u128 = 200;
-// 0.02
-const TREASURY_ID: &str = "treasury1.near";
-const CONTRACT_ID: &str = "mjol.near";
-const REMOVER_ACCOUNT_ID: &str = "cleaner.mjol.near";
+// fee config of a freshly deployed or migrated contract, 0.02
+const DEFAULT_TREASURY_FEE: u32 = 200;
+const DEFAULT_TREASURY_ID: &str = "treasury1.near";
+// cap of the royalties registered for NFT contracts without payouts, 0.1
+const DEFAULT_MAX_ROYALTY_BPS: u32 = 1_000;
+// same as the `max_len_payout` asked from NFT contracts
+const MAX_ROYALTY_RECEIVERS: usize = 10;
+
+// a purchase callback that hasn't run by then has failed, its lock can be cleared, 1 day
+const PENDING_SALE_TIMEOUT: u64 = 86_400_000_000_000;
 
 const UID_DELIMITER: &str = ":";
 
@@ -75,6 +185,38 @@ enum StorageKey {
     ListingsSet,
     TokenUIDsByOwnerSet,
     TokenUIDsByOwnerInnerSet { account_id_hash: CryptoHash },
+    Auctions,
+    DutchAuctions,
+    Offers,
+    OffersByToken,
+    OffersByTokenInner { token_uid_hash: CryptoHash },
+    OffersByBuyer,
+    OffersByBuyerInner { account_id_hash: CryptoHash },
+    CollectionOffers,
+    CollectionOffersByContract,
+    CollectionOffersByContractInner { account_id_hash: CryptoHash },
+    Expirations,
+    StorageDeposits,
+    StorageUsed,
+    PricesByContract,
+    PricesByContractInner { account_id_hash: CryptoHash },
+    CollectionFees,
+    FeePromotions,
+    Roles,
+    Paused,
+    VerifiedCollections,
+    TokenUIDsByContract,
+    TokenUIDsByContractInner { account_id_hash: CryptoHash },
+    Prices,
+    SalesByToken,
+    SalesByTokenInner { token_uid_hash: CryptoHash },
+    CollectionStats,
+    CollectionRoyalties,
+    NftAdapters,
+    TokenUIDsByOwnerContract,
+    TokenUIDsByOwnerContractInner { owner_contract_hash: CryptoHash },
+    FtBalances,
+    SaleCounts,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -115,15 +257,40 @@ pub struct ApprovedNFTFull {
     pub reference_url: Option<String>,
     pub collection_metadata: Option<CollectionMetadata>,
     pub price: U128,
+    pub ft_token_id: Option<AccountId>,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct MarketArgs {
     pub json_nft: ApprovedNFT,
+    /// NEP-141 token the price is denominated in, NEAR if not set.
+    #[serde(default)]
+    pub ft_token_id: Option<AccountId>,
+    /// Lists the NFT as an English auction instead of a fixed price sale.
+    #[serde(default)]
+    pub auction: Option<AuctionArgs>,
+    /// Lists the NFT with a price declining over time.
+    #[serde(default)]
+    pub dutch_auction: Option<DutchAuctionArgs>,
+    /// Nanoseconds after which the listing can't be bought and may be cleaned up by anyone.
+    #[serde(default)]
+    pub expires_at: Option<U64>,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+/// `msg` of `ft_transfer_call` when buying a listing priced in a fungible token.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtPurchaseArgs {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    #[serde(default)]
+    pub max_price: Option<U128>,
+    #[serde(default)]
+    pub expected_approval_id: Option<U64>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct TokenData {
     pub owner_id: AccountId,
@@ -131,6 +298,41 @@ pub struct TokenData {
     pub token_id: TokenId,
     pub price: u128,
     pub approval_id: u64,
+    pub ft_token_id: Option<AccountId>,
+    pub expires_at: Option<u64>,
+    /// Set while the NFT transfer of a purchase is in flight.
+    pub pending_sale: Option<PendingSale>,
+    /// Bytes charged to the owner for the listing, released when it is removed.
+    pub storage_bytes: u64,
+}
+
+/// Purchase holding the lock of a listing, kept to refund the buyer if its callback never settles it.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingSale {
+    pub buyer_id: AccountId,
+    pub price: U128,
+    pub started_at: U64,
+}
+
+impl PendingSale {
+    pub fn new(buyer_id: &AccountId, price: U128) -> Self {
+        Self {
+            buyer_id: buyer_id.clone(),
+            price,
+            started_at: U64::from(env::block_timestamp()),
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        env::block_timestamp() >= self.started_at.0 + PENDING_SALE_TIMEOUT
+    }
+}
+
+impl TokenData {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|x| env::block_timestamp() >= x)
+    }
 }
 
 #[near_bindgen]
@@ -141,6 +343,49 @@ pub struct Contract {
     listings: UnorderedSet<TokenUID>,
     uid_to_data: UnorderedMap<TokenUID, TokenData>,
     user_to_uids: UnorderedMap<AccountId, UnorderedSet<TokenUID>>,
+    auctions: UnorderedMap<TokenUID, Auction>,
+    dutch_auctions: UnorderedMap<TokenUID, DutchAuction>,
+    offers: UnorderedMap<u64, Offer>,
+    offers_by_token: UnorderedMap<TokenUID, UnorderedSet<u64>>,
+    offers_by_buyer: UnorderedMap<AccountId, UnorderedSet<u64>>,
+    next_offer_id: u64,
+    collection_offers: UnorderedMap<u64, CollectionOffer>,
+    // ordered by price then id, the best offer is the last one
+    collection_offers_by_contract: UnorderedMap<AccountId, TreeMap<(u128, u64), ()>>,
+    // listings with an expiration, ordered by it
+    expirations: TreeMap<(u64, TokenUID), ()>,
+    storage_deposits: UnorderedMap<AccountId, Balance>,
+    // bytes taken by each lister's listings
+    storage_used: UnorderedMap<AccountId, u64>,
+    // fixed NEAR price listings of each NFT contract, cheapest first
+    prices_by_contract: UnorderedMap<AccountId, TreeMap<(u128, TokenUID), ()>>,
+    fee_config: FeeConfig,
+    // fee overrides in basis points by NFT contract
+    collection_fees: UnorderedMap<AccountId, u32>,
+    fee_promotions: UnorderedMap<u64, FeePromotion>,
+    next_fee_promotion_id: u64,
+    owner_id: AccountId,
+    pending_owner_id: Option<AccountId>,
+    roles: UnorderedMap<AccountId, Vec<Role>>,
+    paused: UnorderedSet<(PauseScope, Option<AccountId>)>,
+    verified_collections: UnorderedMap<AccountId, VerifiedCollection>,
+    verified_only: bool,
+    uids_by_contract: UnorderedMap<AccountId, UnorderedSet<TokenUID>>,
+    // fixed NEAR price listings across all NFT contracts, cheapest first
+    prices: TreeMap<(u128, TokenUID), ()>,
+    sales_by_token: UnorderedMap<TokenUID, Vector<Sale>>,
+    collection_stats: UnorderedMap<AccountId, CollectionStats>,
+    // royalties in basis points by NFT contract, paid when there is no NFT payout
+    collection_royalties: UnorderedMap<AccountId, HashMap<AccountId, u32>>,
+    max_royalty_bps: u32,
+    // how NFTs of each contract are transferred, learned on their first sale
+    nft_adapters: UnorderedMap<AccountId, NftAdapter>,
+    // listings of an owner by NFT contract, for `get_user_nfts` filtered by contract
+    uids_by_owner_contract: UnorderedMap<(AccountId, AccountId), UnorderedSet<TokenUID>>,
+    // (account, token) amounts whose `ft_transfer` failed, withdrawn with `withdraw_ft`
+    ft_balances: UnorderedMap<(AccountId, AccountId), u128>,
+    // every sale of a token, `sales_by_token` only keeps the last ones
+    sale_counts: UnorderedMap<TokenUID, u64>,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
@@ -159,6 +404,42 @@ impl Default for Contract {
             listings: UnorderedSet::new(StorageKey::ListingsSet),
             uid_to_data: UnorderedMap::new(StorageKey::TokenUIDToData),
             user_to_uids: UnorderedMap::new(StorageKey::TokenUIDsByOwnerSet),
+            auctions: UnorderedMap::new(StorageKey::Auctions),
+            dutch_auctions: UnorderedMap::new(StorageKey::DutchAuctions),
+            offers: UnorderedMap::new(StorageKey::Offers),
+            offers_by_token: UnorderedMap::new(StorageKey::OffersByToken),
+            offers_by_buyer: UnorderedMap::new(StorageKey::OffersByBuyer),
+            next_offer_id: 0,
+            collection_offers: UnorderedMap::new(StorageKey::CollectionOffers),
+            collection_offers_by_contract: UnorderedMap::new(StorageKey::CollectionOffersByContract),
+            expirations: TreeMap::new(StorageKey::Expirations),
+            storage_deposits: UnorderedMap::new(StorageKey::StorageDeposits),
+            storage_used: UnorderedMap::new(StorageKey::StorageUsed),
+            prices_by_contract: UnorderedMap::new(StorageKey::PricesByContract),
+            fee_config: FeeConfig {
+                default_fee_bps: DEFAULT_TREASURY_FEE,
+                treasury_id: AccountId::new_unchecked(DEFAULT_TREASURY_ID.to_string()),
+            },
+            collection_fees: UnorderedMap::new(StorageKey::CollectionFees),
+            fee_promotions: UnorderedMap::new(StorageKey::FeePromotions),
+            next_fee_promotion_id: 0,
+            // the account itself owns a migrated contract
+            owner_id: env::current_account_id(),
+            pending_owner_id: None,
+            roles: UnorderedMap::new(StorageKey::Roles),
+            paused: UnorderedSet::new(StorageKey::Paused),
+            verified_collections: UnorderedMap::new(StorageKey::VerifiedCollections),
+            verified_only: false,
+            uids_by_contract: UnorderedMap::new(StorageKey::TokenUIDsByContract),
+            prices: TreeMap::new(StorageKey::Prices),
+            sales_by_token: UnorderedMap::new(StorageKey::SalesByToken),
+            collection_stats: UnorderedMap::new(StorageKey::CollectionStats),
+            collection_royalties: UnorderedMap::new(StorageKey::CollectionRoyalties),
+            max_royalty_bps: DEFAULT_MAX_ROYALTY_BPS,
+            nft_adapters: UnorderedMap::new(StorageKey::NftAdapters),
+            uids_by_owner_contract: UnorderedMap::new(StorageKey::TokenUIDsByOwnerContract),
+            ft_balances: UnorderedMap::new(StorageKey::FtBalances),
+            sale_counts: UnorderedMap::new(StorageKey::SaleCounts),
         }
     }
 }
@@ -166,14 +447,11 @@ impl Default for Contract {
 #[near_bindgen]
 impl Contract {
     #[init(ignore_state)]
-    pub fn new() -> Self {
-        assert_eq!(env::predecessor_account_id().to_string(), CONTRACT_ID);
+    #[private]
+    pub fn new(owner_id: AccountId) -> Self {
         Self {
-            listings_old: Vector::new(StorageKey::Listings),
-            user_to_uids_old: UnorderedMap::new(StorageKey::TokenUIDsByOwner),
-            listings: UnorderedSet::new(StorageKey::ListingsSet),
-            uid_to_data: UnorderedMap::new(StorageKey::TokenUIDToData),
-            user_to_uids: UnorderedMap::new(StorageKey::TokenUIDsByOwnerSet),
+            owner_id,
+            ..Default::default()
         }
     }
 
@@ -190,17 +468,82 @@ impl Contract {
         let nft_contract_id = env::predecessor_account_id();
         assert_ne!(env::signer_account_id(), nft_contract_id, "Cross contract call awaited");
 
+        if let Ok(AcceptOfferArgs { offer_id }) = near_sdk::serde_json::from_str(&msg) {
+            self.accept_offer(nft_contract_id, token_id, owner_id, approval_id, offer_id.0);
+            return;
+        }
+        if let Ok(AcceptCollectionOfferArgs { collection_offer_id }) = near_sdk::serde_json::from_str(&msg) {
+            self.accept_collection_offer(nft_contract_id, token_id, owner_id, approval_id,
+                                         collection_offer_id.0);
+            return;
+        }
+
         let MarketArgs {
-            json_nft
+            json_nft,
+            ft_token_id,
+            auction,
+            dutch_auction,
+            expires_at,
         } = near_sdk::serde_json::from_str(&msg).expect("Not valid MarketArgs");
 
-        let price = json_nft.price;
+        self.assert_not_paused(PauseScope::Listings, &nft_contract_id);
+        assert!(!self.verified_only || self.is_verified(nft_contract_id.clone()),
+                "Only NFTs of verified contracts can be listed");
+
+        assert!(auction.is_none() || dutch_auction.is_none(),
+                "NFT can be listed either on an auction or a dutch auction");
+        if let Some(expires_at) = expires_at {
+            assert!(auction.is_none(), "Auctions end by settlement and can't expire");
+            assert!(expires_at.0 > env::block_timestamp(), "Listing expiration is in the past");
+        }
+
+        let price = if let Some(auction) = &auction {
+            assert!(ft_token_id.is_none(), "Auctions are only supported in NEAR");
+            auction.reserve_price
+        } else if let Some(dutch_auction) = &dutch_auction {
+            dutch_auction.start_price
+        } else {
+            json_nft.price
+        };
 
 
         let new_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
 
-        assert!(self.uid_to_data.get(&new_uid.clone()).is_none(),
-                "This NFT is already on the market");
+        // a re-approved NFT replaces its listing, the old one can't be bought with a stale approval
+        let old_data = self.uid_to_data.get(&new_uid.clone());
+        // measured before the updated listing's indexes are dropped, only the difference is charged
+        let mut initial_storage_usage = None;
+        let mut kept_storage_bytes = 0;
+        if let Some(old_data) = &old_data {
+            assert!(old_data.pending_sale.is_none(), "NFT is being sold");
+            let old_auction = self.auctions.get(&new_uid.clone());
+            let old_bid = old_auction.as_ref().and_then(|x| x.highest_bid.clone());
+
+            if old_data.owner_id == owner_id {
+                assert!(old_bid.is_none(), "Auction already has bids");
+            } else if let Some(bid) = old_bid {
+                // the NFT changed hands, its auction can't be settled anymore
+                Promise::new(bid.bidder_id).transfer(bid.amount.0);
+            }
+
+            let is_fixed_price = old_auction.is_none()
+                && self.dutch_auctions.get(&new_uid.clone()).is_none()
+                && auction.is_none()
+                && dutch_auction.is_none();
+            if old_data.owner_id == owner_id && is_fixed_price {
+                // same seller, the listing is updated in place below
+                initial_storage_usage = Some(env::storage_usage());
+                kept_storage_bytes = old_data.storage_bytes;
+                self.unindex_price(&new_uid, old_data);
+                if let Some(expires_at) = old_data.expires_at {
+                    self.expirations.remove(&(expires_at, new_uid.clone()));
+                }
+            } else {
+                self.remove_nft(old_data.owner_id.clone(), new_uid.clone());
+            }
+        }
+
+        let initial_storage_usage = initial_storage_usage.unwrap_or_else(env::storage_usage);
 
         // update users listing info
         let mut cur_users_token_uids = self
@@ -220,13 +563,46 @@ impl Contract {
         // add new listing to all listings
         self.listings.insert(&new_uid.clone());
 
+        if let Some(auction) = &auction {
+            self.auctions.insert(&new_uid.clone(), &Auction::new(auction));
+        }
+        if let Some(dutch_auction) = &dutch_auction {
+            self.dutch_auctions.insert(&new_uid.clone(), &DutchAuction::new(dutch_auction));
+        }
+
         // add new uid -> TokenData
-        self.uid_to_data.insert(&new_uid.clone(), &TokenData {
+        let token_data = TokenData {
             owner_id: owner_id.clone(),
             nft_contract_id: nft_contract_id.clone(),
             token_id: token_id.clone(),
             price: price.0,
             approval_id: approval_id.clone(),
+            ft_token_id: ft_token_id.clone(),
+            expires_at: expires_at.map(|x| x.0),
+            pending_sale: None,
+            storage_bytes: 0,
+        };
+        self.uid_to_data.insert(&new_uid.clone(), &token_data);
+        self.index_price(&new_uid, &token_data);
+        self.index_by_contract(&new_uid, &nft_contract_id, &owner_id);
+        if let Some(expires_at) = expires_at {
+            self.expirations.insert(&(expires_at.0, new_uid.clone()), &());
+        }
+
+        let storage_usage = env::storage_usage();
+        let storage_bytes = if storage_usage >= initial_storage_usage {
+            self.charge_storage(&owner_id, storage_usage - initial_storage_usage);
+            kept_storage_bytes + (storage_usage - initial_storage_usage)
+        } else {
+            // never more than what was charged for the listing
+            let released = min(initial_storage_usage - storage_usage, kept_storage_bytes);
+            self.release_storage(&owner_id, released);
+            kept_storage_bytes - released
+        };
+        // same size, the charged bytes don't change
+        self.uid_to_data.insert(&new_uid.clone(), &TokenData {
+            storage_bytes,
+            ..token_data
         });
 
         let full_json_nft = ApprovedNFTFull {
@@ -240,32 +616,31 @@ impl Contract {
             reference_url: json_nft.reference_url,
             collection_metadata: json_nft.collection_metadata,
             price: json_nft.price,
+            ft_token_id: ft_token_id.clone(),
         };
 
-        env::log_str(&json!({
-        "type": "nft_on_approve",
-        "data": {
-            "nft_contract_id": nft_contract_id,
-            "token_id": token_id,
-            "approval_id": U64::from(approval_id),
-            "json_nft": to_string(&full_json_nft).unwrap()
+        let listing = ListingEvent {
+            nft_contract_id,
+            token_id,
+            owner_id,
+            approval_id: U64::from(approval_id),
+            price,
+            ft_token_id,
+            json_nft: full_json_nft,
+            auction,
+            dutch_auction,
+            expires_at,
+        };
+        if let Some(old_data) = old_data {
+            MarketEvent::ListingReplaced(vec![ListingReplacedEvent {
+                old_owner_id: old_data.owner_id,
+                old_approval_id: U64::from(old_data.approval_id),
+                old_price: U128::from(old_data.price),
+                listing,
+            }]).emit();
+        } else {
+            MarketEvent::NftOnApprove(vec![listing]).emit();
         }
-        }).to_string());
-    }
-
-    #[payable]
-    #[private]
-    pub fn verify_contract(&mut self,
-                           contract_id: AccountId,
-                           contract_name: String,
-    ) {
-        env::log_str(&json!({
-            "type": "verify_contract",
-            "data": {
-                "contract_id": contract_id.clone(),
-                "contract_name": contract_name.clone()
-            }
-        }).to_string())
     }
 
     #[payable]
@@ -283,21 +658,27 @@ impl Contract {
         let caller_id = env::predecessor_account_id();
 
         assert_eq!(owner_id, caller_id, "You are not the owner of the NFT");
-
-        self.uid_to_data.insert(&nft_uid.clone(), &TokenData {
+        self.assert_not_paused(PauseScope::PriceUpdates, &nft_contract_id);
+        assert!(nft_data.pending_sale.is_none(), "NFT is being sold");
+        assert!(self.auctions.get(&nft_uid.clone()).is_none(),
+                "Price of an auction can't be updated");
+        assert!(self.dutch_auctions.get(&nft_uid.clone()).is_none(),
+                "Price of a dutch auction can't be updated");
+
+        self.unindex_price(&nft_uid, &nft_data);
+        let new_data = TokenData {
             price: price.0,
             ..nft_data
-        });
-
-        env::log_str(&json!({
-            "type": "update_token_price",
-            "data": {
-                "nft_contract_id": nft_contract_id,
-                "token_id": token_id,
-                "owner_id": owner_id,
-                "price": price
-            }
-        }).to_string());
+        };
+        self.uid_to_data.insert(&nft_uid.clone(), &new_data);
+        self.index_price(&nft_uid, &new_data);
+
+        MarketEvent::UpdateTokenPrice(vec![PriceUpdateEvent {
+            nft_contract_id,
+            token_id,
+            owner_id,
+            price,
+        }]).emit();
     }
 
     #[payable]
@@ -314,19 +695,20 @@ impl Contract {
         let caller_id = env::predecessor_account_id();
 
         assert_eq!(owner_id, caller_id);
+        assert!(nft_data.pending_sale.is_none(), "NFT is being sold");
+        if let Some(auction) = self.auctions.get(&nft_uid.clone()) {
+            assert!(auction.highest_bid.is_none(), "Auction already has bids");
+        }
 
         self.remove_nft(owner_id, nft_uid);
 
-        env::log_str(&json!({
-            "type": "remove_from_market",
-            "data": {
-                "nft_contract_id": nft_contract_id,
-                "token_id": token_id,
-                "owner_id": nft_data.owner_id,
-                "approval_id": U64::from(nft_data.approval_id),
-                "price": U128::from(nft_data.price)
-            }
-        }).to_string());
+        MarketEvent::RemoveFromMarket(vec![ListingRemovedEvent {
+            nft_contract_id,
+            token_id,
+            owner_id: nft_data.owner_id,
+            approval_id: U64::from(nft_data.approval_id),
+            price: U128::from(nft_data.price),
+        }]).emit();
     }
 
     #[payable]
@@ -334,83 +716,182 @@ impl Contract {
         &mut self,
         nft_contract_id: AccountId,
         token_id: TokenId,
-        is_payouts_supported: bool,
+        max_price: Option<U128>,
+        expected_approval_id: Option<U64>,
+        check_listing: Option<bool>,
     ) {
         let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
         let nft_data = self.uid_to_data.get(&nft_uid.clone())
             .expect("NFT does not exist.");
 
-        let cur_approval_id: u64 = nft_data.approval_id.clone();
-        let cur_price: U128 = U128::from(nft_data.price.clone());
-        let seller_id = nft_data.owner_id.clone();
         let buyer_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+
+        assert!(nft_data.ft_token_id.is_none(),
+                "NFT is priced in a fungible token, buy it with ft_transfer_call");
+        let cur_price = self.purchase_price(&nft_uid, &nft_data, &buyer_id, max_price,
+                                            expected_approval_id)
+            .unwrap_or_else(|err| env::panic_str(&err));
+        assert!(deposit >= cur_price, "Attached deposit is less than the price {}", cur_price);
+
+        // the price may have dropped since the buyer signed the transaction
+        if deposit > cur_price {
+            Promise::new(buyer_id.clone()).transfer(deposit - cur_price);
+        }
 
-        assert_eq!(U128::from(env::attached_deposit()), cur_price);
-        assert_ne!(seller_id, buyer_id);
-
-        if is_payouts_supported {
-            nft_contract::nft_transfer_payout(
-                buyer_id.clone(),      // receiver_id: ValidAccountId,
-                token_id.clone(),      // token_id: TokenId,
-                Some(cur_approval_id), // approval_id: Option<u64>,
-                Some(cur_price),       // balance: Option<U128>,
-                Some(10u32),           // max_len_payout: Option<u32>
-                nft_contract_id.clone(),
-                1,
-                GAS_FOR_NFT_TRANSFER,
-            ).then(ext_self::resolve_purchase(
-                buyer_id,
-                seller_id,
-                nft_uid,
-                cur_price,
-                env::current_account_id(),
-                NO_DEPOSIT,
-                GAS_FOR_ROYALTIES,
-            ));
+        if check_listing.unwrap_or(false) {
+            self.buy_checked(nft_uid, nft_data, buyer_id, U128::from(cur_price));
         } else {
-            nft_contract::nft_transfer(
-                buyer_id.clone(),      // receiver_id: ValidAccountId,
-                token_id.clone(),      // token_id: TokenId,
-                Some(cur_approval_id), // approval_id: Option<u64>
-                None,
-                nft_contract_id.clone(),
-                1,
-                GAS_FOR_NFT_TRANSFER,
-            ).then(ext_self::resolve_purchase_no_payouts(
-                buyer_id,
-                seller_id,
-                nft_uid,
-                cur_price,
-                env::current_account_id(),
-                NO_DEPOSIT,
-                GAS_FOR_ROYALTIES,
-            ));
+            self.transfer_nft(nft_data, buyer_id, U128::from(cur_price));
         }
     }
 
+    /// NEP-141 receiver, `msg` is `FtPurchaseArgs` of the listing to buy with the transferred tokens.
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let ft_token_id = env::predecessor_account_id();
+
+        let FtPurchaseArgs {
+            nft_contract_id,
+            token_id,
+            max_price,
+            expected_approval_id,
+        } = near_sdk::serde_json::from_str(&msg).expect("Not valid FtPurchaseArgs");
+
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        let nft_data = self.uid_to_data.get(&nft_uid.clone())
+            .expect("NFT does not exist.");
+
+        assert_eq!(nft_data.ft_token_id, Some(ft_token_id.clone()),
+                   "NFT is not priced in this token");
+        let cur_price = self.purchase_price(&nft_uid, &nft_data, &sender_id, max_price,
+                                            expected_approval_id)
+            .unwrap_or_else(|err| env::panic_str(&err));
+        assert!(amount.0 >= cur_price, "Transferred amount is less than the price {}", cur_price);
+
+        self.transfer_nft(nft_data, sender_id, U128::from(cur_price));
+
+        // the price is kept in full, the buyer gets it back with `ft_transfer` if the NFT transfer fails,
+        // the surplus is returned by the token contract
+        PromiseOrValue::Value(U128::from(amount.0 - cur_price))
+    }
+
     #[payable]
     pub fn remove_old_listing(&mut self, token_uid: TokenUID) {
-        assert_eq!(env::predecessor_account_id().to_string(), REMOVER_ACCOUNT_ID);
+        self.assert_role(Role::Cleaner);
         let token_data = self.uid_to_data.get(&token_uid.clone());
 
         if let Some(data) = token_data {
+            assert!(data.pending_sale.is_none(), "NFT is being sold");
+            if let Some(auction) = self.auctions.get(&token_uid.clone()) {
+                if let Some(bid) = auction.highest_bid {
+                    Promise::new(bid.bidder_id).transfer(bid.amount.0);
+                }
+            }
+
             self.remove_nft(data.owner_id.clone(), token_uid.clone());
 
-            env::log_str(&json!({
-            "type": "remove_old_listing",
-            "data": {
-                "nft_contract_id": data.nft_contract_id.clone(),
-                "token_id": data.token_id.clone(),
-                "owner_id": data.owner_id.clone(),
-                "approval_id": U64::from(data.approval_id.clone()),
-                "price": U128::from(data.price.clone())
-            }
-        }).to_string());
+            MarketEvent::RemoveOldListing(vec![ListingRemovedEvent {
+                nft_contract_id: data.nft_contract_id,
+                token_id: data.token_id,
+                owner_id: data.owner_id,
+                approval_id: U64::from(data.approval_id),
+                price: U128::from(data.price),
+            }]).emit();
         } else {
             env::panic_str("Token is not on the market.")
         }
     }
 
+    /// Clears the lock of a purchase whose callback failed, callable by the buyer, the seller
+    /// or a cleaner once `PENDING_SALE_TIMEOUT` has passed. The NFT contract is asked where
+    /// the token is before the buyer is refunded or the seller paid.
+    pub fn clear_pending_sale(&mut self, nft_contract_id: AccountId, token_id: TokenId) -> Promise {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        let nft_data = self.uid_to_data.get(&nft_uid.clone())
+            .expect("NFT does not exist.");
+        let pending_sale = nft_data.pending_sale.clone()
+            .expect("NFT is not being sold");
+        let caller_id = env::predecessor_account_id();
+        if caller_id != nft_data.owner_id && caller_id != pending_sale.buyer_id {
+            self.assert_role(Role::Cleaner);
+        }
+        assert!(pending_sale.is_stale(), "Purchase may still be settled");
+
+        let adapter = self.nft_adapter(&nft_contract_id);
+        let gas_for_resolve = Contract::gas_for_resolve(&nft_data.ft_token_id, adapter.max_len_payout);
+        Contract::check_listing(&nft_data).then(ext_self::resolve_clear_pending_sale(
+            nft_uid,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            Gas(gas_for_resolve.0 + BASE_GAS.0),
+        ))
+    }
+
+    /// Pays the seller of a stale purchase if the buyer got the NFT, refunds the buyer wherever
+    /// else it is. The lock is kept if the NFT contract doesn't answer.
+    #[private]
+    pub fn resolve_clear_pending_sale(&mut self, nft_uid: TokenUID) {
+        let nft_data = self.uid_to_data.get(&nft_uid.clone())
+            .expect("NFT does not exist.");
+        let pending_sale = nft_data.pending_sale.clone()
+            .expect("NFT is not being sold");
+
+        let token = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<Option<NftToken>>(&value)
+                .ok().flatten(),
+            _ => None,
+        };
+        let token = token.unwrap_or_else(|| env::panic_str("NFT can't be found, the lock is kept"));
+
+        if token.owner_id == pending_sale.buyer_id {
+            self.remove_listing_of(&nft_data.owner_id, &nft_uid);
+            // `nft_payout` now answers for the buyer as the owner, the registered royalties are paid
+            self.pay_out(pending_sale.buyer_id, nft_data.owner_id, nft_uid,
+                         pending_sale.price, nft_data.ft_token_id, None);
+        } else {
+            // the seller may have moved the NFT elsewhere, only the buyer is owed anything then.
+            // The payment of an auction is its highest bid, refunded here as the buyer's
+            self.fail_purchase(pending_sale.buyer_id, nft_data.owner_id, nft_uid,
+                               pending_sale.price, nft_data.ft_token_id);
+        }
+    }
+
+    /// Removes up to `limit` expired listings, callable by anyone.
+    pub fn cleanup_expired(&mut self, limit: u64) -> u64 {
+        let now = env::block_timestamp();
+        // a purchase started before the expiration settles it in its callback
+        let expired: Vec<(TokenUID, TokenData)> = self.expirations.iter()
+            .map(|(key, _)| key)
+            .take_while(|(expires_at, _)| *expires_at <= now)
+            .map(|(_, uid)| {
+                let data = self.uid_to_data.get(&uid.clone()).unwrap();
+                (uid, data)
+            })
+            .filter(|(_, data)| data.pending_sale.is_none())
+            .take(limit as usize)
+            .collect();
+
+        let mut removed: Vec<TokenUID> = vec![];
+        for (uid, data) in expired {
+            self.remove_nft(data.owner_id, uid.clone());
+            removed.push(uid);
+        }
+
+        let count = removed.len() as u64;
+        if count > 0 {
+            MarketEvent::CleanupExpired(removed.into_iter()
+                .map(|nft_uid| ListingExpiredEvent { nft_uid })
+                .collect()).emit();
+        }
+
+        count
+    }
+
     pub fn get_nfts(self, from: u64, limit: u64) -> MarketData {
         let size = self.listings.len() as u64;
         let mut res = vec![];
@@ -425,8 +906,11 @@ impl Contract {
         let real_from = max(real_to as i64 - limit as i64, 0 as i64) as usize;
 
         for i in (real_from..real_to).rev() {
-            res.push(self.uid_to_data
-                .get(&self.listings.as_vector().get(i as u64).unwrap()).unwrap())
+            let uid = self.listings.as_vector().get(i as u64).unwrap();
+            let data = self.uid_to_data.get(&uid).unwrap();
+            if !data.is_expired() {
+                res.push(self.with_current_price(&uid, data))
+            }
         }
 
         MarketData {
@@ -436,23 +920,61 @@ impl Contract {
         }
     }
 
-    pub fn get_user_nfts(self, owner_id: AccountId) -> Vec<TokenData> {
-        let all_uids = self.user_to_uids
-            .get(&owner_id.clone());
-
-        return if let Some(uids) = all_uids {
-            uids.iter().map(|x| {
-                self.uid_to_data.get(&x.clone()).unwrap()
-            }).collect()
+    /// Page of `owner_id` listings, optionally of a single NFT contract. `total_count` is the
+    /// number of matching listings, expired ones are counted but not returned.
+    pub fn get_user_nfts(
+        self,
+        owner_id: AccountId,
+        from: u64,
+        limit: u64,
+        nft_contract_id: Option<AccountId>,
+    ) -> MarketData {
+        let uids = if let Some(uids) = self.user_to_uids.get(&owner_id) {
+            uids
         } else {
-            vec![]
+            return MarketData {
+                tokens: vec![],
+                has_next_batch: false,
+                total_count: 0,
+            };
+        };
+
+        let uids = match nft_contract_id {
+            Some(nft_contract_id) => match self.uids_by_owner_contract.get(&(owner_id, nft_contract_id)) {
+                Some(uids) => uids,
+                None => return MarketData {
+                    tokens: vec![],
+                    has_next_batch: false,
+                    total_count: 0,
+                },
+            },
+            None => uids,
         };
+        let total_count = uids.len();
+        let page: Vec<TokenUID> = (from..min(from.saturating_add(limit), total_count))
+            .map(|i| uids.as_vector().get(i).unwrap())
+            .collect();
+
+        // a uid without data is skipped rather than failing the whole view
+        let tokens = page.iter()
+            .filter_map(|uid| self.uid_to_data.get(uid).map(|data| (uid, data)))
+            .filter(|(_, data)| !data.is_expired())
+            .map(|(uid, data)| self.with_current_price(uid, data))
+            .collect();
+
+        MarketData {
+            tokens,
+            has_next_batch: from.saturating_add(limit) < total_count,
+            total_count,
+        }
     }
 
     pub fn get_nft_price(self, token_uid: TokenUID) -> Option<u128> {
         let token = self.uid_to_data.get(&token_uid);
         if let Some(token) = token {
-            return Some(token.price);
+            if !token.is_expired() {
+                return Some(self.current_price(&token_uid, &token));
+            }
         }
         None
     }
@@ -464,166 +986,117 @@ impl Contract {
         seller_id: AccountId,
         nft_uid: TokenUID,
         price: U128,
-    ) {
+        ft_token_id: Option<AccountId>,
+    ) -> bool {
         assert_eq!(env::promise_results_count(), 1);
         match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
-            PromiseResult::Failed => env::panic_str("NFT Transfer failed. Try again."),
+            PromiseResult::Failed => {
+                self.fail_purchase(buyer_id, seller_id, nft_uid, price, ft_token_id);
+                return false;
+            }
             PromiseResult::Successful(_) => ()
         }
 
-        self.remove_nft(seller_id.clone(), nft_uid.clone());
-
-        let payout_option = promise_result_as_success().and_then(|value| {
-
-            // If Payout is struct with payout field than get it
-            let res = near_sdk::serde_json::from_slice::<PayoutStruct>(&value);
-            if res.is_ok() {
-                res.ok().and_then(|payout| {
-                    Contract::check_payouts(price, payout.payout)
-                })
-            } else {
-                near_sdk::serde_json::from_slice::<Payout>(&value).ok().and_then(|payout| {
-                    Contract::check_payouts(price, payout)
-                })
-            }
-        });
-
-        let payout = if let Some(payout_option) = payout_option {
-            payout_option
-        } else {
-            let treasury_fee = price.0 * TREASURY_FEE / 10_000u128;
-            Promise::new(seller_id.clone())
-                .transfer(price.0 - treasury_fee);
-            Promise::new(AccountId::new_unchecked(TREASURY_ID.to_string()))
-                .transfer(treasury_fee);
-
-            let mut log_payout = Payout::new();
-            log_payout.insert(seller_id.clone(), U128::from(price.0 - treasury_fee));
-
-            env::log_str(
-                &json!({
-                    "type": "resolve_purchase",
-                    "data": {
-                        "price": U128::from(price),
-                        "buyer_id": buyer_id,
-                        "seller_id": seller_id,
-                        "nft_uid": nft_uid,
-                        "payout": log_payout
-                    }
-                }).to_string()
-            );
-            return;
-        };
+        self.remove_listing_of(&seller_id, &nft_uid);
 
-        // 2% fee for treasury
-        let treasury_fee = price.0 * TREASURY_FEE / 10_000u128;
-
-        for (receiver_id, amount) in payout.clone() {
-            if receiver_id == seller_id {
-                Promise::new(receiver_id).transfer(amount.0 - treasury_fee);
-                Promise::new(AccountId::new_unchecked(TREASURY_ID.to_string())).transfer(treasury_fee);
-            } else {
-                Promise::new(receiver_id).transfer(amount.0);
-            }
-        }
-
-        let mut log_payout = payout.clone();
-        *log_payout
-            .get_mut(&seller_id.clone()).unwrap() = U128::from(
-            log_payout[&seller_id.clone()].0 - treasury_fee
-        );
-        env::log_str(
-            &json!({
-                    "type": "resolve_purchase",
-                    "data": {
-                        "price": U128::from(price),
-                        "buyer_id": buyer_id,
-                        "seller_id": seller_id,
-                        "nft_uid": nft_uid,
-                        "payout": log_payout
-                    }
-                }).to_string()
-        );
+        let payout = Contract::parse_payout(price);
+        self.pay_out(buyer_id, seller_id, nft_uid, price, ft_token_id, payout);
+        true
     }
 
     #[private]
     pub fn resolve_purchase_no_payouts(&mut self, buyer_id: AccountId, seller_id: AccountId,
-                                       nft_uid: TokenUID, price: U128) {
+                                       nft_uid: TokenUID, price: U128,
+                                       ft_token_id: Option<AccountId>) -> bool {
         assert_eq!(env::promise_results_count(), 1);
         match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
-            PromiseResult::Failed => env::panic_str("NFT Transfer failed. Try again."),
+            PromiseResult::Failed => {
+                self.fail_purchase(buyer_id, seller_id, nft_uid, price, ft_token_id);
+                return false;
+            }
             PromiseResult::Successful(_) => ()
         }
 
-        self.remove_nft(seller_id.clone(), nft_uid.clone());
+        self.remove_listing_of(&seller_id, &nft_uid);
 
-        let treasury_fee = price.0 * TREASURY_FEE / 10_000u128;
-        Promise::new(seller_id.clone())
-            .transfer(price.0 - treasury_fee);
-        Promise::new(AccountId::new_unchecked(TREASURY_ID.to_string()))
-            .transfer(treasury_fee);
+        self.pay_out(buyer_id, seller_id, nft_uid, price, ft_token_id, None);
+        true
+    }
 
-        let mut log_payout = Payout::new();
-        log_payout.insert(seller_id.clone(), U128::from(price.0 - treasury_fee));
+    #[private]
+    pub fn migrate_token_data(&mut self, from: u64, bs: u64) {
+        #[derive(BorshDeserialize)]
+        struct OldTokenData {
+            owner_id: AccountId,
+            nft_contract_id: AccountId,
+            token_id: TokenId,
+            price: u128,
+            approval_id: u64,
+        }
 
-        env::log_str(
-            &json!({
-                    "type": "resolve_purchase",
-                    "data": {
-                        "price": U128::from(price),
-                        "buyer_id": buyer_id,
-                        "seller_id": seller_id,
-                        "nft_uid": nft_uid,
-                        "payout": log_payout
-                    }
-                }).to_string()
-        );
+        let size = self.uid_to_data.len();
+        env::log_str(&format!("TOKEN DATA -> {}/{}", min(from + bs, size), size));
+
+        for i in from..min(from + bs, size) {
+            let uid_raw = self.uid_to_data.keys_as_vector().get_raw(i).unwrap();
+            let old: OldTokenData = BorshDeserialize::try_from_slice(
+                &self.uid_to_data.values_as_vector().get_raw(i).unwrap()
+            ).expect("Token data is already migrated.");
+
+            let new_data = TokenData {
+                owner_id: old.owner_id,
+                nft_contract_id: old.nft_contract_id,
+                token_id: old.token_id,
+                price: old.price,
+                approval_id: old.approval_id,
+                ft_token_id: None,
+                expires_at: None,
+                pending_sale: None,
+                storage_bytes: 0,
+            };
+            self.uid_to_data.insert_raw(&uid_raw, &new_data.try_to_vec().unwrap());
+        }
     }
 
-    // #[init(ignore_state)]
-    // #[private]
-    // pub fn migrate() -> Self {
-    //     #[derive(BorshDeserialize)]
-    //     struct Old {
-    //         listings: Vector<TokenUID>,
-    //         uid_to_data: UnorderedMap<TokenUID, TokenData>,
-    //         user_to_uids: UnorderedMap<AccountId, Vector<TokenUID>>,
-    //     }
-    //
-    //     let prev_state: Old = env::state_read().expect("No such state.");
-    //
-    //     let mut new_listings: UnorderedSet<TokenUID> = UnorderedSet::new(StorageKey::ListingsSet);
-    //     let mut new_user_to_uids: UnorderedMap<AccountId, UnorderedSet<TokenUID>> =
-    //         UnorderedMap::new(StorageKey::TokenUIDsByOwnerSet);
-    //
-    //     for (acc, uids) in prev_state.user_to_uids.iter() {
-    //         let mut new_uids = new_user_to_uids
-    //             .get(&acc.clone())
-    //             .unwrap_or_else(|| {
-    //                 UnorderedSet::new(StorageKey::TokenUIDsByOwnerInnerSet {
-    //                     account_id_hash: hash_account_id(&acc.clone())
-    //                 }
-    //                 )
-    //             });
-    //         for uid in uids.iter() {
-    //             new_uids.insert(&uid.clone());
-    //         }
-    //
-    //         new_user_to_uids.insert(&acc.clone(), &new_uids);
-    //     }
-    //
-    //     for listing in prev_state.listings.iter() {
-    //         new_listings.insert(&listing.clone());
-    //     }
-    //
-    //     Self {
-    //         listings: new_listings,
-    //         uid_to_data: prev_state.uid_to_data,
-    //         user_to_uids: new_user_to_uids,
-    //     }
-    // }
+    #[init(ignore_state)]
+    #[private]
+    pub fn migrate() -> Self {
+        #[derive(BorshDeserialize)]
+        struct Old {
+            listings_old: Vector<TokenUID>,
+            user_to_uids_old: UnorderedMap<AccountId, Vector<TokenUID>>,
+            listings: UnorderedSet<TokenUID>,
+            uid_to_data: UnorderedMap<TokenUID, TokenData>,
+            user_to_uids: UnorderedMap<AccountId, UnorderedSet<TokenUID>>,
+        }
+
+        let prev_state: Old = env::state_read().expect("No such state.");
+
+        Self {
+            listings_old: prev_state.listings_old,
+            user_to_uids_old: prev_state.user_to_uids_old,
+            listings: prev_state.listings,
+            uid_to_data: prev_state.uid_to_data,
+            user_to_uids: prev_state.user_to_uids,
+            ..Default::default()
+        }
+    }
+
+    /// Adds listings made before an index existed to the derived indexes.
+    #[private]
+    pub fn migrate_indexes(&mut self, from: u64, bs: u64) {
+        let size = self.listings.len();
+        env::log_str(&format!("INDEXES -> {}/{}", min(from + bs, size), size));
+
+        for i in from..min(from + bs, size) {
+            let uid = self.listings.as_vector().get(i).unwrap();
+            let nft_data = self.uid_to_data.get(&uid).unwrap();
+            self.index_price(&uid, &nft_data);
+            self.index_by_contract(&uid, &nft_data.nft_contract_id, &nft_data.owner_id);
+        }
+    }
 
     #[init(ignore_state)]
     #[private]
@@ -643,6 +1116,7 @@ impl Contract {
             user_to_uids: UnorderedMap::new(StorageKey::TokenUIDsByOwnerSet),
             listings_old: prev_state.listings,
             user_to_uids_old: prev_state.user_to_uids,
+            ..Default::default()
         }
     }
 
@@ -689,6 +1163,7 @@ impl Contract {
             user_to_uids: prev_state.user_to_uids,
             user_to_uids_old: prev_state.user_to_uids_old,
             listings_old: prev_state.listings_old,
+            ..Default::default()
         }
     }
 
@@ -721,9 +1196,283 @@ impl Contract {
             user_to_uids: prev_state.user_to_uids,
             user_to_uids_old: prev_state.user_to_uids_old,
             listings_old: prev_state.listings_old,
+            ..Default::default()
+        }
+    }
+
+    // price `buyer_id` has to pay for the listing, or why it can't be bought
+    fn purchase_price(
+        &self,
+        nft_uid: &TokenUID,
+        nft_data: &TokenData,
+        buyer_id: &AccountId,
+        max_price: Option<U128>,
+        expected_approval_id: Option<U64>,
+    ) -> Result<u128, String> {
+        let cur_price = self.current_price(nft_uid, nft_data);
+
+        if self.is_paused(PauseScope::Purchases, nft_data.nft_contract_id.clone()) {
+            return Err("Purchases are paused".to_string());
+        }
+        if nft_data.is_expired() {
+            return Err("Listing has expired".to_string());
+        }
+        if nft_data.pending_sale.is_some() {
+            return Err("NFT is already being sold".to_string());
+        }
+        if self.auctions.get(nft_uid).is_some() {
+            return Err("NFT is on auction, place a bid instead".to_string());
+        }
+        if &nft_data.owner_id == buyer_id {
+            return Err("You are the owner of the NFT".to_string());
+        }
+        if let Some(expected_approval_id) = expected_approval_id {
+            if nft_data.approval_id != expected_approval_id.0 {
+                return Err("NFT was re-approved since the purchase was signed".to_string());
+            }
+        }
+        if let Some(max_price) = max_price {
+            if cur_price > max_price.0 {
+                return Err(format!("Price {} is above max_price", cur_price));
+            }
+        }
+
+        Ok(cur_price)
+    }
+
+    fn transfer_nft(
+        &mut self,
+        nft_data: TokenData,
+        buyer_id: AccountId,
+        price: U128,
+    ) -> Promise {
+        assert!(nft_data.pending_sale.is_none(), "NFT is already being sold");
+
+        // locked until `resolve_purchase` removes the listing
+        let nft_uid: TokenUID = format!("{}{}{}", nft_data.nft_contract_id, UID_DELIMITER, nft_data.token_id);
+        self.uid_to_data.insert(&nft_uid, &TokenData {
+            pending_sale: Some(PendingSale::new(&buyer_id, price)),
+            ..nft_data.clone()
+        });
+
+        let adapter = self.nft_adapter(&nft_data.nft_contract_id);
+        let gas_for_sale = Contract::gas_for_sale(&adapter, &nft_data.ft_token_id);
+        assert!(env::prepaid_gas().0 - env::used_gas().0 >= gas_for_sale.0 + BASE_GAS.0,
+                "Not enough gas attached, the sale needs {} more", gas_for_sale.0 + BASE_GAS.0);
+        if adapter.payouts == PayoutSupport::Unknown {
+            return Contract::probe_payouts(nft_uid, nft_data, buyer_id, price, &adapter);
+        }
+        Contract::send_nft(nft_uid, nft_data, buyer_id, price, &adapter)
+    }
+
+    // plain `nft_transfer` only for contracts known to have no payouts
+    fn send_nft(
+        nft_uid: TokenUID,
+        nft_data: TokenData,
+        buyer_id: AccountId,
+        price: U128,
+        adapter: &NftAdapter,
+    ) -> Promise {
+        let TokenData {
+            owner_id: seller_id,
+            nft_contract_id,
+            token_id,
+            approval_id,
+            ft_token_id,
+            ..
+        } = nft_data;
+
+        let gas_for_resolve = Contract::gas_for_resolve(&ft_token_id, adapter.max_len_payout);
+
+        if adapter.payouts != PayoutSupport::Unsupported {
+            nft_contract::nft_transfer_payout(
+                buyer_id.clone(),                // receiver_id: ValidAccountId,
+                token_id.clone(),                // token_id: TokenId,
+                Some(approval_id),               // approval_id: Option<u64>,
+                Some(price),                     // balance: Option<U128>,
+                Some(adapter.max_len_payout),    // max_len_payout: Option<u32>
+                nft_contract_id.clone(),
+                1,
+                Gas(adapter.transfer_gas.0),
+            ).then(ext_self::resolve_purchase(
+                buyer_id,
+                seller_id,
+                nft_uid,
+                price,
+                ft_token_id,
+                env::current_account_id(),
+                NO_DEPOSIT,
+                gas_for_resolve,
+            ))
+        } else {
+            nft_contract::nft_transfer(
+                buyer_id.clone(),      // receiver_id: ValidAccountId,
+                token_id.clone(),      // token_id: TokenId,
+                Some(approval_id),     // approval_id: Option<u64>
+                None,
+                nft_contract_id.clone(),
+                1,
+                Gas(adapter.transfer_gas.0),
+            ).then(ext_self::resolve_purchase_no_payouts(
+                buyer_id,
+                seller_id,
+                nft_uid,
+                price,
+                ft_token_id,
+                env::current_account_id(),
+                NO_DEPOSIT,
+                gas_for_resolve,
+            ))
         }
     }
 
+    fn gas_for_resolve(ft_token_id: &Option<AccountId>, max_len_payout: u32) -> Gas {
+        if ft_token_id.is_some() {
+            // the seller and the registered royalties when there is no NFT payout, and the treasury
+            let receivers = max(max_len_payout, MAX_ROYALTY_RECEIVERS as u32 + 1) + 1;
+            Gas(GAS_FOR_ROYALTIES.0 + GAS_FOR_FT_PAYOUT.0 * receivers as u64)
+        } else {
+            GAS_FOR_ROYALTIES
+        }
+    }
+
+    // sends NEAR or, if `ft_token_id` is set, the fungible token
+    fn transfer_funds(ft_token_id: &Option<AccountId>, receiver_id: AccountId, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        if let Some(ft_token_id) = ft_token_id {
+            // credited to `receiver_id` if it fails, see `withdraw_ft`
+            ft_contract::ft_transfer(
+                receiver_id.clone(),
+                U128::from(amount),
+                None,
+                ft_token_id.clone(),
+                1,
+                GAS_FOR_FT_TRANSFER,
+            ).then(ext_self::resolve_ft_transfer(
+                ft_token_id.clone(),
+                receiver_id,
+                U128::from(amount),
+                env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_RESOLVE_FT_TRANSFER,
+            ));
+        } else {
+            Promise::new(receiver_id).transfer(amount);
+        }
+    }
+
+    // the NFT couldn't be transferred, most likely the approval was revoked or the token moved
+    fn fail_purchase(
+        &mut self,
+        buyer_id: AccountId,
+        seller_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
+        ft_token_id: Option<AccountId>,
+    ) {
+        Contract::transfer_funds(&ft_token_id, buyer_id.clone(), price.0);
+        self.remove_listing_of(&seller_id, &nft_uid);
+
+        MarketEvent::PurchaseFailed(vec![PurchaseFailedEvent {
+            nft_uid,
+            buyer_id,
+            seller_id,
+            price,
+            ft_token_id,
+        }]).emit();
+    }
+
+    fn nft_contract_of(nft_uid: &TokenUID) -> AccountId {
+        // account ids can't contain the delimiter, token ids can
+        let (nft_contract_id, _) = nft_uid.split_once(UID_DELIMITER).unwrap();
+        AccountId::new_unchecked(nft_contract_id.to_string())
+    }
+
+    // removes the listing unless it's gone or was replaced by another owner meanwhile
+    fn remove_listing_of(&mut self, owner_id: &AccountId, nft_uid: &TokenUID) {
+        if let Some(nft_data) = self.uid_to_data.get(nft_uid) {
+            if &nft_data.owner_id == owner_id {
+                self.remove_nft(owner_id.clone(), nft_uid.clone());
+            }
+        }
+    }
+
+    // payout returned by `nft_transfer_payout`, if it is valid for the price
+    fn parse_payout(price: U128) -> Option<Payout> {
+        promise_result_as_success().and_then(|value| Contract::payout_from_slice(price, &value))
+    }
+
+    fn payout_from_slice(price: U128, value: &[u8]) -> Option<Payout> {
+        // If Payout is struct with payout field than get it
+        let res = near_sdk::serde_json::from_slice::<PayoutStruct>(value);
+        if res.is_ok() {
+            res.ok().and_then(|payout| {
+                Contract::check_payouts(price, payout.payout)
+            })
+        } else {
+            near_sdk::serde_json::from_slice::<Payout>(value).ok().and_then(|payout| {
+                Contract::check_payouts(price, payout)
+            })
+        }
+    }
+
+    // distributes the price of a sold NFT, the treasury fee is taken from the seller's share
+    fn pay_out(
+        &mut self,
+        buyer_id: AccountId,
+        seller_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
+        ft_token_id: Option<AccountId>,
+        payout: Option<Payout>,
+    ) {
+        let treasury_id = self.fee_config.treasury_id.clone();
+        let nft_contract_id = Contract::nft_contract_of(&nft_uid);
+        let fee_bps = self.effective_fee_bps(&nft_contract_id);
+        // without an NFT payout the royalties registered in the market are paid
+        let payout = payout.unwrap_or_else(|| {
+            let treasury_fee = mul_div(price.0, fee_bps as u128, 10_000);
+            self.registry_payout(&nft_contract_id, &seller_id, price.0, treasury_fee)
+        });
+        // taken from the seller's share, never more than what the NFT payout left them
+        let seller_amount = payout.get(&seller_id).map_or(0, |x| x.0);
+        let treasury_fee = min(mul_div(price.0, fee_bps as u128, 10_000), seller_amount);
+
+        self.record_sale(&nft_uid, Sale {
+            buyer_id: buyer_id.clone(),
+            seller_id: seller_id.clone(),
+            price,
+            ft_token_id: ft_token_id.clone(),
+            treasury_fee: U128::from(treasury_fee),
+            sold_at: U64::from(env::block_timestamp()),
+        });
+
+        for (receiver_id, amount) in payout.clone() {
+            if receiver_id == seller_id {
+                Contract::transfer_funds(&ft_token_id, receiver_id, amount.0 - treasury_fee);
+            } else {
+                Contract::transfer_funds(&ft_token_id, receiver_id, amount.0);
+            }
+        }
+        Contract::transfer_funds(&ft_token_id, treasury_id, treasury_fee);
+
+        let mut log_payout = payout;
+        if let Some(amount) = log_payout.get_mut(&seller_id) {
+            amount.0 -= treasury_fee;
+        }
+        MarketEvent::ResolvePurchase(vec![PurchaseEvent {
+            nft_uid,
+            buyer_id,
+            seller_id,
+            price,
+            ft_token_id,
+            payout: log_payout,
+            treasury_fee: U128::from(treasury_fee),
+        }]).emit();
+    }
+
     fn check_payouts(
         price: U128,
         payout: Payout,
@@ -740,6 +1489,7 @@ impl Contract {
     }
 
     fn remove_nft(&mut self, owner_id: AccountId, nft_uid: TokenUID) {
+
         // delete from owner's listings
         let mut cur_users_token_uids = self
             .user_to_uids
@@ -759,6 +1509,177 @@ impl Contract {
         assert!(self.listings.remove(&nft_uid.clone()));
 
         // delete info about NFT
-        assert!(self.uid_to_data.remove(&nft_uid.clone()).is_some());
+        let nft_data = self.uid_to_data.remove(&nft_uid.clone()).unwrap();
+        if let Some(expires_at) = nft_data.expires_at {
+            self.expirations.remove(&(expires_at, nft_uid.clone()));
+        }
+        self.unindex_price(&nft_uid, &nft_data);
+        self.unindex_by_contract(&nft_uid, &nft_data.nft_contract_id, &owner_id);
+
+        self.auctions.remove(&nft_uid.clone());
+        self.dutch_auctions.remove(&nft_uid.clone());
+
+        // exactly what was charged, bids and purchase locks grew the listing at the market's expense
+        self.release_storage(&owner_id, nft_data.storage_bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::serde_json::json;
+    use near_sdk::test_utils::{accounts, testing_env_with_promise_results, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const PRICE: Balance = 1_000_000_000_000_000_000_000_000;
+
+    // `accounts(0)` is the market, `accounts(3)` the NFT contract
+    fn context(predecessor_id: AccountId, attached_deposit: Balance) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .predecessor_account_id(predecessor_id.clone())
+            .signer_account_id(predecessor_id)
+            .attached_deposit(attached_deposit);
+        builder
+    }
+
+    fn setup() -> Contract {
+        testing_env!(context(accounts(0), 0).build());
+        let mut contract = Contract::new(accounts(0));
+        deposit_storage(&mut contract, accounts(1));
+        contract
+    }
+
+    fn deposit_storage(contract: &mut Contract, account_id: AccountId) {
+        testing_env!(context(account_id, PRICE).build());
+        contract.storage_deposit(None, None);
+    }
+
+    fn list(contract: &mut Contract, owner_id: AccountId) {
+        list_expiring(contract, owner_id, None);
+    }
+
+    fn list_expiring(contract: &mut Contract, owner_id: AccountId, expires_at: Option<u64>) {
+        testing_env!(context(owner_id.clone(), 0).predecessor_account_id(accounts(3)).build());
+        let msg = json!({
+            "json_nft": { "title": "NFT", "copies": "1", "price": PRICE.to_string() },
+            "expires_at": expires_at.map(|x| x.to_string()),
+        });
+        contract.nft_on_approve("1".to_string(), owner_id, 0, msg.to_string());
+    }
+
+    fn uid() -> TokenUID {
+        format!("{}{}{}", accounts(3), UID_DELIMITER, "1")
+    }
+
+    fn buy(contract: &mut Contract, buyer_id: AccountId) {
+        testing_env!(context(buyer_id, PRICE).build());
+        contract.buy(accounts(3), "1".to_string(), None, None, None);
+    }
+
+    fn resolve_clear_pending_sale(contract: &mut Contract, token_owner_id: AccountId) {
+        testing_env_with_promise_results(
+            context(accounts(0), 0).block_timestamp(PENDING_SALE_TIMEOUT).build(),
+            PromiseResult::Successful(json!({ "owner_id": token_owner_id }).to_string().into_bytes()),
+        );
+        contract.resolve_clear_pending_sale(uid());
+    }
+
+    #[test]
+    fn locked_listing_can_not_be_bought() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1));
+        buy(&mut contract, accounts(2));
+
+        let nft_data = contract.uid_to_data.get(&uid()).unwrap();
+        assert_eq!(nft_data.pending_sale.as_ref().unwrap().buyer_id, accounts(2));
+        assert_eq!(contract.purchase_price(&uid(), &nft_data, &accounts(4), None, None),
+                   Err("NFT is already being sold".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Purchase may still be settled")]
+    fn lock_is_kept_until_the_timeout() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1));
+        buy(&mut contract, accounts(2));
+
+        testing_env!(context(accounts(2), 0).block_timestamp(PENDING_SALE_TIMEOUT - 1).build());
+        contract.clear_pending_sale(accounts(3), "1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a Cleaner can call this method")]
+    fn others_can_not_clear_the_lock() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1));
+        buy(&mut contract, accounts(2));
+
+        testing_env!(context(accounts(4), 0).block_timestamp(PENDING_SALE_TIMEOUT).build());
+        contract.clear_pending_sale(accounts(3), "1".to_string());
+    }
+
+    #[test]
+    fn buyer_clears_the_lock_of_an_unsold_nft() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1));
+        buy(&mut contract, accounts(2));
+
+        testing_env!(context(accounts(2), 0).block_timestamp(PENDING_SALE_TIMEOUT).build());
+        contract.clear_pending_sale(accounts(3), "1".to_string());
+        // the seller still holds the NFT, the buyer is refunded and the listing removed
+        resolve_clear_pending_sale(&mut contract, accounts(1));
+
+        assert!(contract.uid_to_data.get(&uid()).is_none());
+        assert!(contract.sale_counts.get(&uid()).is_none());
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(0));
+    }
+
+    #[test]
+    fn lock_of_a_delivered_nft_pays_the_seller() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1));
+        buy(&mut contract, accounts(2));
+
+        testing_env!(context(accounts(1), 0).block_timestamp(PENDING_SALE_TIMEOUT).build());
+        contract.clear_pending_sale(accounts(3), "1".to_string());
+        resolve_clear_pending_sale(&mut contract, accounts(2));
+
+        assert!(contract.uid_to_data.get(&uid()).is_none());
+        assert_eq!(contract.sale_counts.get(&uid()), Some(1));
+    }
+
+    #[test]
+    fn new_owner_replaces_the_listing() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1));
+        deposit_storage(&mut contract, accounts(4));
+        // the NFT was transferred outside the market and approved again by its new owner
+        list(&mut contract, accounts(4));
+
+        let nft_data = contract.uid_to_data.get(&uid()).unwrap();
+        assert_eq!(nft_data.owner_id, accounts(4));
+        assert_eq!(contract.storage_used.get(&accounts(4)), Some(nft_data.storage_bytes));
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(0));
+        assert!(contract.user_to_uids.get(&accounts(1)).is_none_or(|x| !x.contains(&uid())));
+    }
+
+    #[test]
+    fn updated_listing_is_charged_the_difference() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1));
+        let listed_bytes = contract.uid_to_data.get(&uid()).unwrap().storage_bytes;
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(listed_bytes));
+
+        // an expiration grows the listing, only the added bytes are charged
+        list_expiring(&mut contract, accounts(1), Some(PENDING_SALE_TIMEOUT));
+        let expiring_bytes = contract.uid_to_data.get(&uid()).unwrap().storage_bytes;
+        assert!(expiring_bytes > listed_bytes);
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(expiring_bytes));
+
+        list(&mut contract, accounts(1));
+        assert_eq!(contract.uid_to_data.get(&uid()).unwrap().storage_bytes, listed_bytes);
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(listed_bytes));
     }
 }
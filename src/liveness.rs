@@ -0,0 +1,220 @@
+use crate::*;
+
+const GAS_FOR_NFT_VIEW: Gas = Gas(BASE_GAS.0);
+const GAS_FOR_RESOLVE_REFRESH: Gas = Gas(BASE_GAS.0 * 2u64);
+// left to `resolve_checked_buy` itself, on top of the sale it goes on with
+const GAS_FOR_RESOLVE_CHECKED_BUY: Gas = Gas(BASE_GAS.0 * 4u64);
+
+/// Fields of the NEP-171 `nft_token` view the listing is checked against.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub(crate) struct NftToken {
+    pub owner_id: AccountId,
+    // NEP-178, not returned by every contract
+    #[serde(default)]
+    approved_account_ids: Option<HashMap<AccountId, u64>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshStatus {
+    Valid,
+    // the market was re-approved without a listing message, the new approval id is kept
+    Corrected,
+    Removed,
+    // the NFT contract didn't answer, the listing is left as is
+    Unknown,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Checks the listing against the NFT contract, callable by anyone. Listings whose token
+    /// was transferred, burned or unapproved are removed.
+    pub fn refresh_listing(&mut self, nft_contract_id: AccountId, token_id: TokenId) -> Promise {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        let nft_data = self.uid_to_data.get(&nft_uid.clone())
+            .expect("NFT does not exist.");
+        assert!(nft_data.pending_sale.is_none(), "NFT is being sold");
+
+        Contract::check_listing(&nft_data).then(ext_self::resolve_refresh_listing(
+            nft_uid,
+            nft_data.owner_id,
+            nft_data.approval_id,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_REFRESH,
+        ))
+    }
+
+    #[private]
+    pub fn resolve_refresh_listing(
+        &mut self,
+        nft_uid: TokenUID,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> RefreshStatus {
+        let nft_data = match self.uid_to_data.get(&nft_uid.clone()) {
+            Some(nft_data) => nft_data,
+            None => return RefreshStatus::Unknown,
+        };
+        // relisted or being sold since the check was started
+        if nft_data.owner_id != owner_id || nft_data.approval_id != approval_id || nft_data.pending_sale.is_some() {
+            return RefreshStatus::Unknown;
+        }
+
+        let status = self.apply_liveness(&nft_uid, nft_data.clone());
+        MarketEvent::RefreshListing(vec![ListingRefreshedEvent {
+            nft_contract_id: nft_data.nft_contract_id,
+            token_id: nft_data.token_id,
+            owner_id,
+            approval_id: U64::from(approval_id),
+            status,
+        }]).emit();
+
+        status
+    }
+
+    /// Goes on with a `buy` made with `check_listing` once the listing is confirmed,
+    /// refunds the buyer otherwise.
+    #[private]
+    pub fn resolve_checked_buy(
+        &mut self,
+        buyer_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
+    ) {
+        // locked by `buy`, nothing else could have changed it
+        let nft_data = TokenData {
+            pending_sale: None,
+            ..self.uid_to_data.get(&nft_uid.clone()).unwrap()
+        };
+        self.uid_to_data.insert(&nft_uid, &nft_data);
+
+        let status = self.apply_liveness(&nft_uid, nft_data.clone());
+        MarketEvent::RefreshListing(vec![ListingRefreshedEvent {
+            nft_contract_id: nft_data.nft_contract_id.clone(),
+            token_id: nft_data.token_id.clone(),
+            owner_id: nft_data.owner_id.clone(),
+            approval_id: U64::from(nft_data.approval_id),
+            status,
+        }]).emit();
+
+        // an admin may have changed the adapter meanwhile, the listing is kept then
+        let gas_for_sale = Contract::gas_for_sale(&self.nft_adapter(&nft_data.nft_contract_id), &None);
+        let gas_left = env::prepaid_gas().0 - env::used_gas().0;
+        if status == RefreshStatus::Removed || gas_left < gas_for_sale.0 + BASE_GAS.0 {
+            Promise::new(buyer_id.clone()).transfer(price.0);
+            MarketEvent::PurchaseFailed(vec![PurchaseFailedEvent {
+                nft_uid,
+                buyer_id,
+                seller_id: nft_data.owner_id,
+                price,
+                ft_token_id: None,
+            }]).emit();
+            return;
+        }
+
+        let nft_data = self.uid_to_data.get(&nft_uid).unwrap();
+        self.transfer_nft(nft_data, buyer_id, price);
+    }
+}
+
+impl Contract {
+    pub(crate) fn check_listing(nft_data: &TokenData) -> Promise {
+        nft_contract::nft_token(
+            nft_data.token_id.clone(),
+            nft_data.nft_contract_id.clone(),
+            NO_DEPOSIT,
+            GAS_FOR_NFT_VIEW,
+        ).and(nft_contract::nft_is_approved(
+            nft_data.token_id.clone(),
+            env::current_account_id(),
+            Some(nft_data.approval_id),
+            nft_data.nft_contract_id.clone(),
+            NO_DEPOSIT,
+            GAS_FOR_NFT_VIEW,
+        ))
+    }
+
+    // locks the listing until `resolve_checked_buy` confirms it with the NFT contract
+    pub(crate) fn buy_checked(
+        &mut self,
+        nft_uid: TokenUID,
+        nft_data: TokenData,
+        buyer_id: AccountId,
+        price: U128,
+    ) -> Promise {
+        self.uid_to_data.insert(&nft_uid, &TokenData {
+            pending_sale: Some(PendingSale::new(&buyer_id, price)),
+            ..nft_data.clone()
+        });
+        // the payouts are probed in the callback if the adapter is removed meanwhile
+        let adapter = NftAdapter {
+            payouts: PayoutSupport::Unknown,
+            ..self.nft_adapter(&nft_data.nft_contract_id)
+        };
+        let gas_for_sale = Contract::gas_for_sale(&adapter, &None);
+
+        Contract::check_listing(&nft_data).then(ext_self::resolve_checked_buy(
+            buyer_id,
+            nft_uid,
+            price,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            Gas(gas_for_sale.0 + GAS_FOR_RESOLVE_CHECKED_BUY.0),
+        ))
+    }
+
+    // reads the results of `check_listing` and removes or corrects the listing accordingly
+    fn apply_liveness(&mut self, nft_uid: &TokenUID, nft_data: TokenData) -> RefreshStatus {
+        let token = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                match near_sdk::serde_json::from_slice::<Option<NftToken>>(&value) {
+                    Ok(token) => token,
+                    Err(_) => return RefreshStatus::Unknown,
+                }
+            }
+            _ => return RefreshStatus::Unknown,
+        };
+
+        let status = match token {
+            // burned
+            None => RefreshStatus::Removed,
+            Some(token) if token.owner_id != nft_data.owner_id => RefreshStatus::Removed,
+            Some(NftToken { approved_account_ids: Some(approvals), .. }) => {
+                match approvals.get(&env::current_account_id()) {
+                    None => RefreshStatus::Removed,
+                    Some(&approval_id) if approval_id != nft_data.approval_id => {
+                        self.uid_to_data.insert(nft_uid, &TokenData {
+                            approval_id,
+                            ..nft_data.clone()
+                        });
+                        RefreshStatus::Corrected
+                    }
+                    Some(_) => RefreshStatus::Valid,
+                }
+            }
+            Some(_) => match env::promise_result(1) {
+                PromiseResult::Successful(value) => {
+                    match near_sdk::serde_json::from_slice::<bool>(&value) {
+                        Ok(true) => RefreshStatus::Valid,
+                        Ok(false) => RefreshStatus::Removed,
+                        Err(_) => RefreshStatus::Unknown,
+                    }
+                }
+                _ => RefreshStatus::Unknown,
+            },
+        };
+
+        if status == RefreshStatus::Removed {
+            // the auction can't be settled without the NFT
+            if let Some(bid) = self.auctions.get(nft_uid).and_then(|x| x.highest_bid) {
+                Promise::new(bid.bidder_id).transfer(bid.amount.0);
+            }
+            self.remove_nft(nft_data.owner_id, nft_uid.clone());
+        }
+
+        status
+    }
+}
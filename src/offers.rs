@@ -0,0 +1,661 @@
+use crate::*;
+
+// expired collection offers looked at for the best one before giving up
+const MAX_BEST_OFFER_SCAN: usize = 50;
+
+/// `msg` of `nft_approve` accepting an offer instead of listing the NFT.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptOfferArgs {
+    pub offer_id: U64,
+}
+
+/// `msg` of `nft_approve` selling the NFT to a collection offer.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptCollectionOfferArgs {
+    pub collection_offer_id: U64,
+}
+
+/// Accepted offer on its way to the buyer, passed on to the payout probe's callback.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferSale {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub approval_id: U64,
+    pub buyer_id: AccountId,
+    pub seller_id: AccountId,
+    pub price: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Offer {
+    pub offer_id: U64,
+    pub buyer_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub amount: U128,
+    pub expires_at: Option<U64>,
+}
+
+impl Offer {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|x| env::block_timestamp() >= x.0)
+    }
+}
+
+/// Offer to buy `quantity` of any tokens of a collection for `price` each.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOffer {
+    pub offer_id: U64,
+    pub buyer_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub price: U128,
+    pub quantity: u32,
+    pub expires_at: Option<U64>,
+}
+
+impl CollectionOffer {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|x| env::block_timestamp() >= x.0)
+    }
+}
+
+pub(crate) fn hash_token_uid(token_uid: &TokenUID) -> CryptoHash {
+    let mut hash = CryptoHash::default();
+    hash.copy_from_slice(&env::sha256(token_uid.as_bytes()));
+    hash
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn make_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        expires_at: Option<U64>,
+    ) -> U64 {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        let buyer_id = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+
+        self.assert_not_paused(PauseScope::Purchases, &nft_contract_id);
+        assert!(amount > 0, "Offer requires a deposit");
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "Offer expiration is in the past");
+        }
+        if let Some(nft_data) = self.uid_to_data.get(&nft_uid.clone()) {
+            assert_ne!(nft_data.owner_id, buyer_id, "You are the owner of the NFT");
+        }
+
+        let offer = Offer {
+            offer_id: U64::from(self.next_offer_id),
+            buyer_id: buyer_id.clone(),
+            nft_contract_id: nft_contract_id.clone(),
+            token_id: token_id.clone(),
+            amount: U128::from(amount),
+            expires_at,
+        };
+        self.next_offer_id += 1;
+        self.add_offer(&offer);
+
+        let offer_id = offer.offer_id;
+        MarketEvent::MakeOffer(vec![offer]).emit();
+
+        offer_id
+    }
+
+    pub fn cancel_offer(&mut self, offer_id: U64) {
+        let offer = self.offers.get(&offer_id.0)
+            .expect("Offer does not exist.");
+
+        // expired offers can be cleaned up by anyone, the deposit still goes to the buyer
+        assert!(env::predecessor_account_id() == offer.buyer_id || offer.is_expired(),
+                "You are not the owner of the offer");
+
+        self.remove_offer(&offer);
+        Promise::new(offer.buyer_id.clone()).transfer(offer.amount.0);
+
+        MarketEvent::CancelOffer(vec![offer]).emit();
+    }
+
+    #[payable]
+    pub fn make_collection_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        price: U128,
+        quantity: u32,
+        expires_at: Option<U64>,
+    ) -> U64 {
+        let buyer_id = env::predecessor_account_id();
+
+        self.assert_not_paused(PauseScope::Purchases, &nft_contract_id);
+        assert!(price.0 > 0, "Offer price must be positive");
+        assert!(quantity > 0, "Offer quantity must be positive");
+        assert_eq!(env::attached_deposit(), price.0 * quantity as u128,
+                   "Attached deposit must be price * quantity");
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "Offer expiration is in the past");
+        }
+
+        let offer = CollectionOffer {
+            offer_id: U64::from(self.next_offer_id),
+            buyer_id,
+            nft_contract_id: nft_contract_id.clone(),
+            price,
+            quantity,
+            expires_at,
+        };
+        self.next_offer_id += 1;
+
+        let initial_storage_usage = env::storage_usage();
+        let mut contract_offers = self.collection_offers_by_contract
+            .get(&nft_contract_id.clone())
+            .unwrap_or_else(|| {
+                TreeMap::new(StorageKey::CollectionOffersByContractInner {
+                    account_id_hash: hash_account_id(&nft_contract_id.clone())
+                })
+            });
+        contract_offers.insert(&(offer.price.0, offer.offer_id.0), &());
+        self.collection_offers_by_contract.insert(&nft_contract_id, &contract_offers);
+        self.collection_offers.insert(&offer.offer_id.0, &offer);
+        self.charge_storage(&offer.buyer_id, env::storage_usage() - initial_storage_usage);
+
+        let offer_id = offer.offer_id;
+        MarketEvent::MakeCollectionOffer(vec![offer]).emit();
+
+        offer_id
+    }
+
+    pub fn cancel_collection_offer(&mut self, collection_offer_id: U64) {
+        let offer = self.collection_offers.get(&collection_offer_id.0)
+            .expect("Offer does not exist.");
+
+        assert!(env::predecessor_account_id() == offer.buyer_id || offer.is_expired(),
+                "You are not the owner of the offer");
+
+        self.remove_collection_offer(&offer);
+        Promise::new(offer.buyer_id.clone()).transfer(offer.price.0 * offer.quantity as u128);
+
+        MarketEvent::CancelCollectionOffer(vec![offer]).emit();
+    }
+
+    pub fn get_offers_for_token(
+        self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        from: u64,
+        limit: u64,
+    ) -> Vec<Offer> {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        if let Some(offer_ids) = self.offers_by_token.get(&nft_uid) {
+            self.offers_page(offer_ids.as_vector(), from, limit)
+        } else {
+            vec![]
+        }
+    }
+
+    pub fn get_offers_by_buyer(self, buyer_id: AccountId, from: u64, limit: u64) -> Vec<Offer> {
+        if let Some(offer_ids) = self.offers_by_buyer.get(&buyer_id) {
+            self.offers_page(offer_ids.as_vector(), from, limit)
+        } else {
+            vec![]
+        }
+    }
+
+    /// Collection offers of `nft_contract_id`, highest priced first.
+    pub fn get_collection_offers(self, nft_contract_id: AccountId, from: u64, limit: u64) -> Vec<CollectionOffer> {
+        if let Some(offer_keys) = self.collection_offers_by_contract.get(&nft_contract_id) {
+            offer_keys.iter_rev()
+                .skip(from as usize)
+                .take(limit as usize)
+                .filter_map(|((_, offer_id), _)| self.collection_offers.get(&offer_id))
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Highest priced collection offer that is not expired.
+    pub fn get_best_collection_offer(self, nft_contract_id: AccountId) -> Option<CollectionOffer> {
+        self.collection_offers_by_contract.get(&nft_contract_id).and_then(|offer_keys| {
+            offer_keys.iter_rev()
+                .take(MAX_BEST_OFFER_SCAN)
+                .filter_map(|((_, offer_id), _)| self.collection_offers.get(&offer_id))
+                .find(|x| !x.is_expired())
+        })
+    }
+
+    #[private]
+    pub fn resolve_offer(
+        &mut self,
+        buyer_id: AccountId,
+        seller_id: AccountId,
+        nft_uid: TokenUID,
+        price: U128,
+    ) {
+        assert_eq!(env::promise_results_count(), 1);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Failed => {
+                // the offer was taken off the market on accept, its escrow goes back to the buyer
+                Promise::new(buyer_id.clone()).transfer(price.0);
+                MarketEvent::ResolveOfferFailed(vec![PurchaseFailedEvent {
+                    nft_uid,
+                    buyer_id,
+                    seller_id,
+                    price,
+                    ft_token_id: None,
+                }]).emit();
+                return;
+            }
+            PromiseResult::Successful(_) => ()
+        }
+
+        // the NFT is gone, so is its listing. One locked by a purchase started meanwhile is left
+        // to its callback, which fails the transfer and refunds the buyer
+        if let Some(nft_data) = self.uid_to_data.get(&nft_uid.clone()).filter(|x| x.pending_sale.is_none()) {
+            // a bid placed after the offer was accepted can't win the NFT anymore
+            if let Some(bid) = self.auctions.get(&nft_uid).and_then(|x| x.highest_bid) {
+                Promise::new(bid.bidder_id).transfer(bid.amount.0);
+            }
+            self.remove_nft(nft_data.owner_id, nft_uid.clone());
+        }
+
+        let payout = Contract::parse_payout(price);
+        self.pay_out(buyer_id, seller_id, nft_uid, price, None, payout);
+    }
+
+    /// Records what `nft_payout` told about the contract and goes on with the transfer of an
+    /// accepted offer. The offer's escrow is refunded if the probe failed.
+    #[private]
+    pub fn resolve_offer_probe(&mut self, sale: OfferSale, transfer_gas: U64) {
+        let adapter = match self.learn_payouts(&sale.nft_contract_id, sale.price) {
+            Some(adapter) => adapter,
+            None => {
+                Promise::new(sale.buyer_id.clone()).transfer(sale.price.0);
+                MarketEvent::ResolveOfferFailed(vec![PurchaseFailedEvent {
+                    nft_uid: format!("{}{}{}", sale.nft_contract_id, UID_DELIMITER, sale.token_id),
+                    buyer_id: sale.buyer_id,
+                    seller_id: sale.seller_id,
+                    price: sale.price,
+                    ft_token_id: None,
+                }]).emit();
+                return;
+            }
+        };
+        let transfer_gas = U64::from(min(adapter.transfer_gas.0, transfer_gas.0));
+
+        Contract::send_nft_to_offer(sale, &NftAdapter { transfer_gas, ..adapter });
+    }
+}
+
+impl Contract {
+    pub(crate) fn accept_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+        offer_id: u64,
+    ) {
+        let offer = self.offers.get(&offer_id)
+            .expect("Offer does not exist.");
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+
+        assert_eq!(offer.nft_contract_id, nft_contract_id, "Offer is made for another NFT");
+        assert_eq!(offer.token_id, token_id, "Offer is made for another NFT");
+        self.assert_not_paused(PauseScope::Purchases, &nft_contract_id);
+        assert!(!offer.is_expired(), "Offer has expired");
+        assert_ne!(offer.buyer_id, owner_id);
+        if let Some(nft_data) = self.uid_to_data.get(&nft_uid.clone()) {
+            assert!(nft_data.pending_sale.is_none(), "NFT is being sold");
+        }
+        if let Some(auction) = self.auctions.get(&nft_uid.clone()) {
+            assert!(auction.highest_bid.is_none(), "Auction already has bids");
+        }
+
+        self.remove_offer(&offer);
+
+        MarketEvent::AcceptOffer(vec![AcceptOfferEvent {
+            offer_id: offer.offer_id,
+            nft_contract_id: nft_contract_id.clone(),
+            token_id: token_id.clone(),
+            owner_id: owner_id.clone(),
+            approval_id: U64::from(approval_id),
+            buyer_id: offer.buyer_id.clone(),
+            price: offer.amount,
+        }]).emit();
+
+        self.transfer_nft_to_offer(OfferSale {
+            nft_contract_id,
+            token_id,
+            approval_id: U64::from(approval_id),
+            buyer_id: offer.buyer_id,
+            seller_id: owner_id,
+            price: offer.amount,
+        });
+    }
+
+    pub(crate) fn accept_collection_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+        collection_offer_id: u64,
+    ) {
+        let mut offer = self.collection_offers.get(&collection_offer_id)
+            .expect("Offer does not exist.");
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+
+        assert_eq!(offer.nft_contract_id, nft_contract_id, "Offer is made for another collection");
+        self.assert_not_paused(PauseScope::Purchases, &nft_contract_id);
+        assert!(!offer.is_expired(), "Offer has expired");
+        assert_ne!(offer.buyer_id, owner_id);
+        if let Some(nft_data) = self.uid_to_data.get(&nft_uid.clone()) {
+            assert!(nft_data.pending_sale.is_none(), "NFT is being sold");
+        }
+        if let Some(auction) = self.auctions.get(&nft_uid) {
+            assert!(auction.highest_bid.is_none(), "Auction already has bids");
+        }
+
+        // one token of the offer is taken right away, a failed transfer refunds it to the buyer
+        offer.quantity -= 1;
+        if offer.quantity == 0 {
+            self.remove_collection_offer(&offer);
+        } else {
+            self.collection_offers.insert(&collection_offer_id, &offer);
+        }
+
+        MarketEvent::AcceptCollectionOffer(vec![AcceptCollectionOfferEvent {
+            offer_id: offer.offer_id,
+            nft_contract_id: nft_contract_id.clone(),
+            token_id: token_id.clone(),
+            owner_id: owner_id.clone(),
+            approval_id: U64::from(approval_id),
+            buyer_id: offer.buyer_id.clone(),
+            price: offer.price,
+            quantity_left: offer.quantity,
+        }]).emit();
+
+        self.transfer_nft_to_offer(OfferSale {
+            nft_contract_id,
+            token_id,
+            approval_id: U64::from(approval_id),
+            buyer_id: offer.buyer_id,
+            seller_id: owner_id,
+            price: offer.price,
+        });
+    }
+
+    fn transfer_nft_to_offer(&self, sale: OfferSale) {
+        let adapter = self.nft_adapter(&sale.nft_contract_id);
+        if adapter.payouts != PayoutSupport::Unknown {
+            Contract::send_nft_to_offer(sale, &adapter);
+            return;
+        }
+
+        Contract::payout_probe(&sale.nft_contract_id, &sale.token_id, sale.price, &adapter)
+            .then(ext_self::resolve_offer_probe(
+                sale,
+                adapter.transfer_gas,
+                env::current_account_id(),
+                NO_DEPOSIT,
+                Gas(adapter.transfer_gas.0 + GAS_FOR_ROYALTIES.0 + BASE_GAS.0),
+            ));
+    }
+
+    fn send_nft_to_offer(sale: OfferSale, adapter: &NftAdapter) {
+        let OfferSale {
+            nft_contract_id,
+            token_id,
+            approval_id,
+            buyer_id,
+            seller_id,
+            price,
+        } = sale;
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+
+        // `resolve_offer` pays the registered royalties when there is no payout
+        let transfer = if adapter.payouts == PayoutSupport::Unsupported {
+            nft_contract::nft_transfer(
+                buyer_id.clone(),
+                token_id,
+                Some(approval_id.0),
+                None,
+                nft_contract_id,
+                1,
+                Gas(adapter.transfer_gas.0),
+            )
+        } else {
+            nft_contract::nft_transfer_payout(
+                buyer_id.clone(),                // receiver_id: ValidAccountId,
+                token_id,                        // token_id: TokenId,
+                Some(approval_id.0),             // approval_id: Option<u64>,
+                Some(price),                     // balance: Option<U128>,
+                Some(adapter.max_len_payout),    // max_len_payout: Option<u32>
+                nft_contract_id,
+                1,
+                Gas(adapter.transfer_gas.0),
+            )
+        };
+        transfer.then(ext_self::resolve_offer(
+            buyer_id,
+            seller_id,
+            nft_uid,
+            price,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_ROYALTIES,
+        ));
+    }
+
+    fn offers_page(&self, offer_ids: &Vector<u64>, from: u64, limit: u64) -> Vec<Offer> {
+        (from..min(from.saturating_add(limit), offer_ids.len()))
+            .filter_map(|i| self.offers.get(&offer_ids.get(i).unwrap()))
+            .collect()
+    }
+
+    // the buyer pays for the storage of the offer, like sellers do for their listings
+    fn add_offer(&mut self, offer: &Offer) {
+        let initial_storage_usage = env::storage_usage();
+        let nft_uid: TokenUID = format!("{}{}{}", offer.nft_contract_id, UID_DELIMITER, offer.token_id);
+
+        let mut token_offers = self.offers_by_token
+            .get(&nft_uid.clone())
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::OffersByTokenInner {
+                    token_uid_hash: hash_token_uid(&nft_uid)
+                })
+            });
+        token_offers.insert(&offer.offer_id.0);
+        self.offers_by_token.insert(&nft_uid, &token_offers);
+
+        let mut buyer_offers = self.offers_by_buyer
+            .get(&offer.buyer_id.clone())
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::OffersByBuyerInner {
+                    account_id_hash: hash_account_id(&offer.buyer_id.clone())
+                })
+            });
+        buyer_offers.insert(&offer.offer_id.0);
+        self.offers_by_buyer.insert(&offer.buyer_id.clone(), &buyer_offers);
+
+        self.offers.insert(&offer.offer_id.0, offer);
+
+        self.charge_storage(&offer.buyer_id, env::storage_usage() - initial_storage_usage);
+    }
+
+    fn remove_offer(&mut self, offer: &Offer) {
+        let initial_storage_usage = env::storage_usage();
+        let nft_uid: TokenUID = format!("{}{}{}", offer.nft_contract_id, UID_DELIMITER, offer.token_id);
+
+        if let Some(mut token_offers) = self.offers_by_token.get(&nft_uid.clone()) {
+            token_offers.remove(&offer.offer_id.0);
+            self.offers_by_token.insert(&nft_uid, &token_offers);
+        }
+
+        if let Some(mut buyer_offers) = self.offers_by_buyer.get(&offer.buyer_id.clone()) {
+            buyer_offers.remove(&offer.offer_id.0);
+            self.offers_by_buyer.insert(&offer.buyer_id.clone(), &buyer_offers);
+        }
+
+        assert!(self.offers.remove(&offer.offer_id.0).is_some());
+
+        self.release_storage(&offer.buyer_id, initial_storage_usage.saturating_sub(env::storage_usage()));
+    }
+
+    fn remove_collection_offer(&mut self, offer: &CollectionOffer) {
+        let initial_storage_usage = env::storage_usage();
+        if let Some(mut contract_offers) = self.collection_offers_by_contract
+            .get(&offer.nft_contract_id.clone()) {
+            contract_offers.remove(&(offer.price.0, offer.offer_id.0));
+            self.collection_offers_by_contract.insert(&offer.nft_contract_id.clone(), &contract_offers);
+        }
+
+        assert!(self.collection_offers.remove(&offer.offer_id.0).is_some());
+        self.release_storage(&offer.buyer_id, initial_storage_usage.saturating_sub(env::storage_usage()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::mock::VmAction;
+    use near_sdk::serde_json::json;
+    use near_sdk::test_utils::{accounts, get_created_receipts, testing_env_with_promise_results, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const PRICE: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn context(predecessor_id: AccountId, attached_deposit: Balance) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .predecessor_account_id(predecessor_id.clone())
+            .signer_account_id(predecessor_id)
+            .attached_deposit(attached_deposit);
+        builder
+    }
+
+    // `accounts(2)` makes offers for tokens of the NFT contract `accounts(3)` owned by `accounts(1)`,
+    // known to support payouts
+    fn setup() -> Contract {
+        testing_env!(context(accounts(0), 0).build());
+        let mut contract = Contract::new(accounts(0));
+        contract.set_nft_adapter(accounts(3), PayoutSupport::Supported, None, None);
+        testing_env!(context(accounts(2), PRICE).build());
+        contract.storage_deposit(None, None);
+        contract
+    }
+
+    fn uid(token_id: &str) -> TokenUID {
+        format!("{}{}{}", accounts(3), UID_DELIMITER, token_id)
+    }
+
+    fn approve(contract: &mut Contract, token_id: &str, msg: near_sdk::serde_json::Value) {
+        testing_env!(context(accounts(1), 0).predecessor_account_id(accounts(3)).build());
+        contract.nft_on_approve(token_id.to_string(), accounts(1), 0, msg.to_string());
+    }
+
+    // methods called on the NFT contract by the last call
+    fn nft_calls() -> Vec<String> {
+        get_created_receipts().into_iter()
+            .filter(|receipt| receipt.receiver_id == accounts(3))
+            .flat_map(|receipt| receipt.actions)
+            .filter_map(|action| match action {
+                VmAction::FunctionCall { method_name, .. } => Some(method_name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn resolve_offer(contract: &mut Contract, token_id: &str, result: PromiseResult) {
+        testing_env_with_promise_results(context(accounts(0), 0).build(), result);
+        contract.resolve_offer(accounts(2), accounts(1), uid(token_id), U128::from(PRICE));
+    }
+
+    fn payout() -> PromiseResult {
+        let payout = json!({ "payout": { accounts(1).to_string(): PRICE.to_string() } });
+        PromiseResult::Successful(payout.to_string().into_bytes())
+    }
+
+    fn make_offer(contract: &mut Contract, token_id: &str) -> U64 {
+        testing_env!(context(accounts(2), PRICE).build());
+        contract.make_offer(accounts(3), token_id.to_string(), None)
+    }
+
+    #[test]
+    fn accepted_offer_is_sold_to_the_buyer() {
+        let mut contract = setup();
+        let offer_id = make_offer(&mut contract, "1");
+
+        approve(&mut contract, "1", json!({ "offer_id": offer_id }));
+        assert!(contract.offers.get(&offer_id.0).is_none());
+        assert_eq!(nft_calls(), vec!["nft_transfer_payout".to_string()]);
+
+        resolve_offer(&mut contract, "1", payout());
+        assert_eq!(contract.sale_counts.get(&uid("1")), Some(1));
+    }
+
+    #[test]
+    fn failed_transfer_refunds_the_offer() {
+        let mut contract = setup();
+        let offer_id = make_offer(&mut contract, "1");
+        approve(&mut contract, "1", json!({ "offer_id": offer_id }));
+
+        resolve_offer(&mut contract, "1", PromiseResult::Failed);
+        assert!(contract.sale_counts.get(&uid("1")).is_none());
+        let refund = get_created_receipts().into_iter()
+            .filter(|receipt| receipt.receiver_id == accounts(2))
+            .flat_map(|receipt| receipt.actions)
+            .any(|action| action == VmAction::Transfer { deposit: PRICE });
+        assert!(refund);
+    }
+
+    #[test]
+    #[should_panic(expected = "Offer is made for another NFT")]
+    fn offer_is_only_accepted_for_its_token() {
+        let mut contract = setup();
+        let offer_id = make_offer(&mut contract, "1");
+        approve(&mut contract, "2", json!({ "offer_id": offer_id }));
+    }
+
+    fn make_collection_offer(contract: &mut Contract, quantity: u32) -> U64 {
+        testing_env!(context(accounts(2), PRICE * quantity as u128).build());
+        contract.make_collection_offer(accounts(3), U128::from(PRICE), quantity, None)
+    }
+
+    #[test]
+    fn collection_offer_takes_one_token_per_accept() {
+        let mut contract = setup();
+        let offer_id = make_collection_offer(&mut contract, 2);
+
+        approve(&mut contract, "1", json!({ "collection_offer_id": offer_id }));
+        assert_eq!(contract.collection_offers.get(&offer_id.0).unwrap().quantity, 1);
+        assert_eq!(nft_calls(), vec!["nft_transfer_payout".to_string()]);
+        resolve_offer(&mut contract, "1", payout());
+        assert_eq!(contract.sale_counts.get(&uid("1")), Some(1));
+
+        // the last token removes the offer
+        approve(&mut contract, "2", json!({ "collection_offer_id": offer_id }));
+        assert!(contract.collection_offers.get(&offer_id.0).is_none());
+        assert_eq!(nft_calls(), vec!["nft_transfer_payout".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Offer is made for another collection")]
+    fn collection_offer_is_only_accepted_for_its_contract() {
+        let mut contract = setup();
+        let offer_id = make_collection_offer(&mut contract, 1);
+
+        testing_env!(context(accounts(1), 0).predecessor_account_id(accounts(4)).build());
+        let msg = json!({ "collection_offer_id": offer_id });
+        contract.nft_on_approve("1".to_string(), accounts(1), 0, msg.to_string());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PauseScope {
+    Listings,
+    Purchases,
+    PriceUpdates,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Pause {
+    pub scope: PauseScope,
+    // `None` pauses the scope for every NFT contract
+    pub nft_contract_id: Option<AccountId>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Removing listings, cancelling offers and refunds are never paused.
+    pub fn pause(&mut self, scope: PauseScope, nft_contract_id: Option<AccountId>) {
+        self.assert_role(Role::Pauser);
+        if self.paused.insert(&(scope, nft_contract_id.clone())) {
+            MarketEvent::Paused(vec![Pause { scope, nft_contract_id }]).emit();
+        }
+    }
+
+    pub fn unpause(&mut self, scope: PauseScope, nft_contract_id: Option<AccountId>) {
+        self.assert_role(Role::Pauser);
+        if self.paused.remove(&(scope, nft_contract_id.clone())) {
+            MarketEvent::Unpaused(vec![Pause { scope, nft_contract_id }]).emit();
+        }
+    }
+
+    /// Whether `scope` is paused for `nft_contract_id`, either globally or for that contract.
+    pub fn is_paused(&self, scope: PauseScope, nft_contract_id: AccountId) -> bool {
+        self.paused.contains(&(scope, None))
+            || self.paused.contains(&(scope, Some(nft_contract_id)))
+    }
+
+    pub fn get_pauses(self) -> Vec<Pause> {
+        self.paused.iter()
+            .map(|(scope, nft_contract_id)| Pause { scope, nft_contract_id })
+            .collect()
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_not_paused(&self, scope: PauseScope, nft_contract_id: &AccountId) {
+        assert!(!self.is_paused(scope, nft_contract_id.clone()),
+                "{:?} are paused for {}", scope, nft_contract_id);
+    }
+}
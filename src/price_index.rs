@@ -0,0 +1,104 @@
+use std::ops::Bound;
+
+use crate::*;
+
+// listings looked at for a floor price before giving up
+const MAX_FLOOR_SCAN: usize = 50;
+
+#[near_bindgen]
+impl Contract {
+    /// Cheapest buyable fixed NEAR price, of one NFT contract or of the whole market.
+    pub fn get_floor_price(self, nft_contract_id: Option<AccountId>) -> Option<U128> {
+        let nft_uids: Vec<TokenUID> = if let Some(nft_contract_id) = nft_contract_id {
+            self.prices_by_contract.get(&nft_contract_id)
+                .map(|prices| prices.iter().take(MAX_FLOOR_SCAN).map(|((_, uid), _)| uid).collect())
+                .unwrap_or_default()
+        } else {
+            self.prices.iter().take(MAX_FLOOR_SCAN).map(|((_, uid), _)| uid).collect()
+        };
+
+        nft_uids.iter()
+            .filter_map(|uid| self.uid_to_data.get(uid))
+            .find(|x| !x.is_expired() && x.pending_sale.is_none())
+            .map(|x| U128::from(x.price))
+    }
+
+    /// Fixed NEAR price listings priced from `min` to `max` inclusive, cheapest first
+    /// unless `ascending` is false. Auctions and fungible token prices are not included.
+    pub fn get_nfts_by_price(
+        self,
+        min: U128,
+        max: U128,
+        from: u64,
+        limit: u64,
+        ascending: bool,
+        nft_contract_id: Option<AccountId>,
+    ) -> Vec<TokenData> {
+        if min.0 > max.0 {
+            return vec![];
+        }
+
+        let contract_prices;
+        let prices = if let Some(nft_contract_id) = nft_contract_id {
+            contract_prices = match self.prices_by_contract.get(&nft_contract_id) {
+                Some(prices) => prices,
+                None => return vec![],
+            };
+            &contract_prices
+        } else {
+            &self.prices
+        };
+
+        let keys: Box<dyn Iterator<Item = ((u128, TokenUID), ())> + '_> = if ascending {
+            Box::new(prices.range((Bound::Included((min.0, String::new())), Bound::Unbounded))
+                .take_while(|((price, _), _)| *price <= max.0))
+        } else if max.0 == u128::MAX {
+            Box::new(prices.iter_rev()
+                .take_while(|((price, _), _)| *price >= min.0))
+        } else {
+            Box::new(prices.iter_rev_from((max.0 + 1, String::new()))
+                .take_while(|((price, _), _)| *price >= min.0))
+        };
+
+        keys.skip(from as usize)
+            .take(limit as usize)
+            .filter_map(|((_, uid), _)| self.uid_to_data.get(&uid))
+            .filter(|x| !x.is_expired())
+            .collect()
+    }
+}
+
+impl Contract {
+    // only fixed NEAR prices are comparable, auctions and fungible token prices are left out
+    fn is_price_indexed(&self, nft_uid: &TokenUID, nft_data: &TokenData) -> bool {
+        nft_data.ft_token_id.is_none()
+            && self.auctions.get(nft_uid).is_none()
+            && self.dutch_auctions.get(nft_uid).is_none()
+    }
+
+    pub(crate) fn index_price(&mut self, nft_uid: &TokenUID, nft_data: &TokenData) {
+        if !self.is_price_indexed(nft_uid, nft_data) {
+            return;
+        }
+
+        let mut prices = self.prices_by_contract
+            .get(&nft_data.nft_contract_id.clone())
+            .unwrap_or_else(|| {
+                TreeMap::new(StorageKey::PricesByContractInner {
+                    account_id_hash: hash_account_id(&nft_data.nft_contract_id.clone())
+                })
+            });
+        prices.insert(&(nft_data.price, nft_uid.clone()), &());
+        self.prices_by_contract.insert(&nft_data.nft_contract_id.clone(), &prices);
+        self.prices.insert(&(nft_data.price, nft_uid.clone()), &());
+    }
+
+    pub(crate) fn unindex_price(&mut self, nft_uid: &TokenUID, nft_data: &TokenData) {
+        if let Some(mut prices) = self.prices_by_contract.get(&nft_data.nft_contract_id.clone()) {
+            if prices.remove(&(nft_data.price, nft_uid.clone())).is_some() {
+                self.prices_by_contract.insert(&nft_data.nft_contract_id.clone(), &prices);
+            }
+        }
+        self.prices.remove(&(nft_data.price, nft_uid.clone()));
+    }
+}
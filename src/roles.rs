@@ -0,0 +1,94 @@
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+    Moderator,
+    FeeManager,
+    Cleaner,
+    Pauser,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Starts the transfer, `new_owner_id` becomes the owner once it calls `accept_ownership`.
+    pub fn transfer_ownership(&mut self, new_owner_id: AccountId) {
+        self.assert_owner();
+        self.pending_owner_id = Some(new_owner_id.clone());
+
+        MarketEvent::OwnershipTransferStarted(vec![OwnershipEvent {
+            owner_id: self.owner_id.clone(),
+            new_owner_id,
+        }]).emit();
+    }
+
+    pub fn accept_ownership(&mut self) {
+        let new_owner_id = env::predecessor_account_id();
+        assert_eq!(self.pending_owner_id.as_ref(), Some(&new_owner_id), "Ownership is not offered to you");
+
+        let old_owner_id = std::mem::replace(&mut self.owner_id, new_owner_id.clone());
+        self.pending_owner_id = None;
+
+        MarketEvent::OwnershipTransferred(vec![OwnershipEvent {
+            owner_id: old_owner_id,
+            new_owner_id,
+        }]).emit();
+    }
+
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_owner();
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if roles.contains(&role) {
+            return;
+        }
+        roles.push(role);
+        self.roles.insert(&account_id, &roles);
+
+        MarketEvent::RoleGranted(vec![RoleEvent { account_id, role }]).emit();
+    }
+
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_owner();
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if !roles.contains(&role) {
+            return;
+        }
+        roles.retain(|x| x != &role);
+        if roles.is_empty() {
+            self.roles.remove(&account_id);
+        } else {
+            self.roles.insert(&account_id, &roles);
+        }
+
+        MarketEvent::RoleRevoked(vec![RoleEvent { account_id, role }]).emit();
+    }
+
+    pub fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        self.roles.get(&account_id).is_some_and(|x| x.contains(&role))
+    }
+
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        self.roles.get(&account_id).unwrap_or_default()
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only the owner can call this method");
+    }
+
+    /// The owner passes every role check.
+    pub(crate) fn assert_role(&self, role: Role) {
+        let account_id = env::predecessor_account_id();
+        assert!(account_id == self.owner_id || self.has_role(account_id, role),
+                "Only the owner or a {:?} can call this method", role);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Royalties in basis points paid on sales made without `nft_transfer_payout`. Set by a
+    /// moderator, or by the NFT contract itself or its creator once it is verified.
+    pub fn set_collection_royalties(&mut self, nft_contract_id: AccountId, royalties: HashMap<AccountId, u32>) {
+        self.assert_royalty_admin(&nft_contract_id);
+        assert!(royalties.len() <= MAX_ROYALTY_RECEIVERS,
+                "Royalties can have at most {} receivers", MAX_ROYALTY_RECEIVERS);
+        let total: u32 = royalties.values().sum();
+        assert!(total <= self.max_royalty_bps,
+                "Royalties can't exceed {} bps", self.max_royalty_bps);
+
+        self.collection_royalties.insert(&nft_contract_id, &royalties);
+
+        MarketEvent::RoyaltiesChange(vec![RoyaltiesChangeEvent {
+            nft_contract_id,
+            royalties: Some(royalties),
+        }]).emit();
+    }
+
+    pub fn remove_collection_royalties(&mut self, nft_contract_id: AccountId) {
+        self.assert_royalty_admin(&nft_contract_id);
+        self.collection_royalties.remove(&nft_contract_id)
+            .expect("Collection has no royalties.");
+
+        MarketEvent::RoyaltiesChange(vec![RoyaltiesChangeEvent {
+            nft_contract_id,
+            royalties: None,
+        }]).emit();
+    }
+
+    /// Registered royalties above a lowered cap are scaled down when paid.
+    pub fn set_max_royalty(&mut self, max_royalty_bps: u32) {
+        self.assert_role(Role::FeeManager);
+        assert!(max_royalty_bps <= 10_000, "Royalties can't exceed 10000 bps");
+        self.max_royalty_bps = max_royalty_bps;
+
+        MarketEvent::MaxRoyaltyChange(vec![MaxRoyaltyChangeEvent { max_royalty_bps }]).emit();
+    }
+
+    pub fn get_collection_royalties(self, nft_contract_id: AccountId) -> Option<HashMap<AccountId, u32>> {
+        self.collection_royalties.get(&nft_contract_id)
+    }
+
+    pub fn get_max_royalty(self) -> u32 {
+        self.max_royalty_bps
+    }
+}
+
+impl Contract {
+    fn assert_royalty_admin(&self, nft_contract_id: &AccountId) {
+        let caller_id = env::predecessor_account_id();
+        let is_verified_creator = self.verified_collections.get(nft_contract_id)
+            .filter(|x| x.revoked_at.is_none())
+            .is_some_and(|x| &caller_id == nft_contract_id || x.creator_id.as_ref() == Some(&caller_id));
+        if !is_verified_creator {
+            self.assert_role(Role::Moderator);
+        }
+    }
+
+    /// Payout of a sale made without `nft_transfer_payout`, the seller gets what is left
+    /// after the registered royalties. Royalties never eat into the treasury fee.
+    pub(crate) fn registry_payout(
+        &self,
+        nft_contract_id: &AccountId,
+        seller_id: &AccountId,
+        price: u128,
+        treasury_fee: u128,
+    ) -> Payout {
+        let royalties = self.collection_royalties.get(nft_contract_id).unwrap_or_default();
+        let total_bps: u32 = royalties.values().sum();
+        // scaled down to the current cap
+        let cap_bps = min(total_bps, self.max_royalty_bps);
+        let available = price - treasury_fee;
+
+        let mut payout = Payout::new();
+        let mut paid: u128 = 0;
+        for (receiver_id, bps) in royalties.into_iter().filter(|_| total_bps > 0) {
+            let amount = min(
+                mul_div(mul_div(price, bps as u128, total_bps as u128), cap_bps as u128, 10_000),
+                available - paid,
+            );
+            if amount > 0 {
+                paid += amount;
+                payout.entry(receiver_id).or_insert(U128(0)).0 += amount;
+            }
+        }
+        payout.entry(seller_id.clone()).or_insert(U128(0)).0 += price - paid;
+
+        payout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn contract_with_royalties(royalties: Vec<(usize, u32)>, max_royalty_bps: u32) -> Contract {
+        testing_env!(VMContextBuilder::new().build());
+        let mut contract = Contract::new(accounts(0));
+        contract.max_royalty_bps = max_royalty_bps;
+        contract.collection_royalties.insert(
+            &accounts(1),
+            &royalties.into_iter().map(|(x, bps)| (accounts(x), bps)).collect(),
+        );
+        contract
+    }
+
+    fn amount(payout: &Payout, x: usize) -> u128 {
+        payout.get(&accounts(x)).map_or(0, |x| x.0)
+    }
+
+    #[test]
+    fn royalties_are_rounded_down_in_favor_of_the_seller() {
+        let contract = contract_with_royalties(vec![(2, 300), (3, 200)], 1_000);
+        let payout = contract.registry_payout(&accounts(1), &accounts(4), 1_001, 20);
+
+        assert_eq!(amount(&payout, 2), 30);
+        assert_eq!(amount(&payout, 3), 20);
+        assert_eq!(amount(&payout, 4), 951);
+    }
+
+    #[test]
+    fn royalties_above_the_cap_are_scaled_down() {
+        let contract = contract_with_royalties(vec![(2, 1_500), (3, 500)], 1_000);
+        let payout = contract.registry_payout(&accounts(1), &accounts(4), 10_000, 200);
+
+        assert_eq!(amount(&payout, 2), 750);
+        assert_eq!(amount(&payout, 3), 250);
+        assert_eq!(amount(&payout, 4), 9_000);
+    }
+
+    #[test]
+    fn royalties_leave_the_treasury_fee_to_the_seller() {
+        let contract = contract_with_royalties(vec![(2, 10_000)], 10_000);
+        let payout = contract.registry_payout(&accounts(1), &accounts(4), 10_000, 200);
+
+        assert_eq!(amount(&payout, 2), 9_800);
+        assert_eq!(amount(&payout, 4), 200);
+    }
+
+    #[test]
+    fn no_royalties_pay_the_seller_in_full() {
+        let contract = contract_with_royalties(vec![], 1_000);
+        let payout = contract.registry_payout(&accounts(1), &accounts(4), 10_000, 200);
+
+        assert_eq!(payout.len(), 1);
+        assert_eq!(amount(&payout, 4), 10_000);
+    }
+
+    #[test]
+    fn large_prices_do_not_overflow() {
+        let contract = contract_with_royalties(vec![(2, 500), (3, 250)], 1_000);
+        let price = u128::MAX;
+        let treasury_fee = mul_div(price, 200, 10_000);
+        let payout = contract.registry_payout(&accounts(1), &accounts(4), price, treasury_fee);
+
+        assert_eq!(amount(&payout, 2), price / 20);
+        assert_eq!(amount(&payout, 3), price / 40);
+        assert_eq!(amount(&payout, 2) + amount(&payout, 3) + amount(&payout, 4), price);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::*;
+
+const HOUR: u64 = 3_600_000_000_000;
+const DAY_HOURS: u64 = 24;
+const WEEK_HOURS: u64 = 7 * 24;
+// older sales of a token are dropped, the market pays for their storage. Every sale is still
+// counted in `sale_counts` and logged as a `resolve_purchase` event
+const MAX_SALES_PER_TOKEN: u64 = 10;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Sale {
+    pub buyer_id: AccountId,
+    pub seller_id: AccountId,
+    pub price: U128,
+    pub ft_token_id: Option<AccountId>,
+    pub treasury_fee: U128,
+    pub sold_at: U64,
+}
+
+/// Trading stats of a collection, only sales in NEAR are counted.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct CollectionStats {
+    pub volume: u128,
+    pub sale_count: u64,
+    pub all_time_high: u128,
+    pub last_sale_at: u64,
+    // (hour since epoch, volume) of the last week, oldest first
+    pub hourly_volume: Vec<(u64, u128)>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionStatsView {
+    pub volume: U128,
+    pub sale_count: u64,
+    pub all_time_high: U128,
+    pub last_sale_at: U64,
+    pub volume_24h: U128,
+    pub volume_7d: U128,
+}
+
+impl CollectionStats {
+    fn add_sale(&mut self, price: u128, now: u64) {
+        self.volume += price;
+        self.sale_count += 1;
+        self.all_time_high = max(self.all_time_high, price);
+        self.last_sale_at = now;
+
+        let hour = now / HOUR;
+        match self.hourly_volume.last_mut() {
+            Some((last_hour, volume)) if *last_hour == hour => *volume += price,
+            _ => self.hourly_volume.push((hour, price)),
+        }
+        self.hourly_volume.retain(|(x, _)| *x + WEEK_HOURS > hour);
+    }
+
+    fn volume_since(&self, hours: u64, now: u64) -> u128 {
+        let hour = now / HOUR;
+        self.hourly_volume.iter()
+            .filter(|(x, _)| *x + hours > hour)
+            .map(|(_, volume)| volume)
+            .sum()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Last 10 sales of a token, oldest first. Older sales are dropped from the market's state,
+    /// `get_sale_count` counts all of them and each was logged as a `resolve_purchase` event.
+    pub fn get_sales_by_token(
+        self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        from: u64,
+        limit: u64,
+    ) -> Vec<Sale> {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        if let Some(sales) = self.sales_by_token.get(&nft_uid) {
+            (from..min(from.saturating_add(limit), sales.len()))
+                .map(|i| sales.get(i).unwrap())
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Number of sales of a token through the market, including the dropped ones.
+    pub fn get_sale_count(self, nft_contract_id: AccountId, token_id: TokenId) -> u64 {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        self.sale_counts.get(&nft_uid).unwrap_or(0)
+    }
+
+    pub fn get_last_sale(self, nft_contract_id: AccountId, token_id: TokenId) -> Option<Sale> {
+        let nft_uid: TokenUID = format!("{}{}{}", nft_contract_id, UID_DELIMITER, token_id);
+        self.sales_by_token.get(&nft_uid)
+            .and_then(|sales| sales.get(sales.len().checked_sub(1)?))
+    }
+
+    pub fn get_collection_stats(self, nft_contract_id: AccountId) -> Option<CollectionStatsView> {
+        let now = env::block_timestamp();
+        self.collection_stats.get(&nft_contract_id).map(|stats| CollectionStatsView {
+            volume: U128::from(stats.volume),
+            sale_count: stats.sale_count,
+            all_time_high: U128::from(stats.all_time_high),
+            last_sale_at: U64::from(stats.last_sale_at),
+            volume_24h: U128::from(stats.volume_since(DAY_HOURS, now)),
+            volume_7d: U128::from(stats.volume_since(WEEK_HOURS, now)),
+        })
+    }
+}
+
+impl Contract {
+    pub(crate) fn record_sale(&mut self, nft_uid: &TokenUID, sale: Sale) {
+        self.sale_counts.insert(nft_uid, &(self.sale_counts.get(nft_uid).unwrap_or(0) + 1));
+
+        let mut sales = self.sales_by_token.get(nft_uid).unwrap_or_else(|| {
+            Vector::new(StorageKey::SalesByTokenInner {
+                token_uid_hash: hash_token_uid(nft_uid)
+            })
+        });
+        if sales.len() >= MAX_SALES_PER_TOKEN {
+            for i in 1..sales.len() {
+                sales.replace(i - 1, &sales.get(i).unwrap());
+            }
+            sales.replace(sales.len() - 1, &sale);
+        } else {
+            sales.push(&sale);
+            self.sales_by_token.insert(nft_uid, &sales);
+        }
+
+        if sale.ft_token_id.is_none() {
+            let nft_contract_id = Contract::nft_contract_of(nft_uid);
+            let mut stats = self.collection_stats.get(&nft_contract_id).unwrap_or_default();
+            stats.add_sale(sale.price.0, sale.sold_at.0);
+            self.collection_stats.insert(&nft_contract_id, &stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sales_of_an_hour_share_a_bucket() {
+        let mut stats = CollectionStats::default();
+        stats.add_sale(100, 10 * HOUR);
+        stats.add_sale(300, 10 * HOUR + HOUR - 1);
+        stats.add_sale(50, 11 * HOUR);
+
+        assert_eq!(stats.hourly_volume, vec![(10, 400), (11, 50)]);
+        assert_eq!(stats.volume, 450);
+        assert_eq!(stats.sale_count, 3);
+        assert_eq!(stats.all_time_high, 300);
+        assert_eq!(stats.last_sale_at, 11 * HOUR);
+    }
+
+    #[test]
+    fn buckets_older_than_a_week_are_dropped() {
+        let mut stats = CollectionStats::default();
+        stats.add_sale(100, 0);
+        stats.add_sale(200, (WEEK_HOURS - 1) * HOUR);
+        assert_eq!(stats.hourly_volume.len(), 2);
+
+        stats.add_sale(300, WEEK_HOURS * HOUR);
+        assert_eq!(stats.hourly_volume, vec![(WEEK_HOURS - 1, 200), (WEEK_HOURS, 300)]);
+        // all-time stats keep every sale
+        assert_eq!(stats.volume, 600);
+    }
+
+    #[test]
+    fn volume_since_counts_whole_hours() {
+        let mut stats = CollectionStats::default();
+        stats.add_sale(100, 0);
+        stats.add_sale(200, 30 * HOUR);
+        stats.add_sale(300, 48 * HOUR);
+        let now = 48 * HOUR + HOUR / 2;
+
+        assert_eq!(stats.volume_since(DAY_HOURS, now), 500);
+        assert_eq!(stats.volume_since(WEEK_HOURS, now), 600);
+        assert_eq!(stats.volume_since(1, now), 300);
+        // nothing sold within a day of a week later
+        assert_eq!(stats.volume_since(DAY_HOURS, now + WEEK_HOURS * HOUR), 0);
+    }
+}
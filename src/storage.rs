@@ -0,0 +1,170 @@
+use near_contract_standards::storage_management::{StorageBalance, StorageBalanceBounds};
+use near_sdk::assert_one_yocto;
+
+use crate::*;
+
+// rough size of a single fixed price listing, required to register
+const LISTING_STORAGE_ESTIMATE: u64 = 1000;
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let mut deposit = env::attached_deposit();
+        let min = self.storage_balance_bounds().min.0;
+        let balance = self.storage_deposits.get(&account_id.clone());
+        if balance.is_none() {
+            assert!(deposit >= min, "Attached deposit is less than the minimum storage balance");
+        }
+
+        if registration_only.unwrap_or(false) {
+            let refund = if balance.is_some() {
+                deposit
+            } else {
+                deposit - min
+            };
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+            deposit -= refund;
+        }
+
+        self.storage_deposits.insert(&account_id.clone(), &(balance.unwrap_or(0) + deposit));
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = self.storage_balance_of(account_id.clone())
+            .expect("Account is not registered");
+
+        let amount = amount.map(|x| x.0).unwrap_or(balance.available.0);
+        assert!(amount <= balance.available.0, "Amount exceeds the available storage balance");
+
+        self.storage_deposits.insert(&account_id.clone(), &(balance.total.0 - amount));
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_deposits.get(&account_id.clone()).map(|total| {
+            let used = self.storage_used.get(&account_id).unwrap_or(0) as u128
+                * env::storage_byte_cost();
+            StorageBalance {
+                total: U128::from(total),
+                available: U128::from(total.saturating_sub(used)),
+            }
+        })
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128::from(LISTING_STORAGE_ESTIMATE as u128 * env::storage_byte_cost()),
+            max: None,
+        }
+    }
+}
+
+impl Contract {
+    /// Adds `bytes` to the storage used by `account_id`, panics if its deposit doesn't cover it.
+    pub(crate) fn charge_storage(&mut self, account_id: &AccountId, bytes: u64) {
+        let used = self.storage_used.get(account_id).unwrap_or(0) + bytes;
+        let total = self.storage_deposits.get(account_id).unwrap_or(0);
+        let required = used as u128 * env::storage_byte_cost();
+
+        assert!(total >= required,
+                "Not enough storage deposit, {} yoctoNEAR is required, use storage_deposit",
+                required);
+
+        self.storage_used.insert(account_id, &used);
+    }
+
+    pub(crate) fn release_storage(&mut self, account_id: &AccountId, bytes: u64) {
+        // accounts that were never charged release nothing
+        if let Some(used) = self.storage_used.get(account_id) {
+            self.storage_used.insert(account_id, &used.saturating_sub(bytes));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn setup(attached_deposit: Balance) -> Contract {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(attached_deposit)
+            .build());
+        Contract::new(accounts(0))
+    }
+
+    fn min_balance() -> Balance {
+        LISTING_STORAGE_ESTIMATE as u128 * env::storage_byte_cost()
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit is less than the minimum storage balance")]
+    fn first_deposit_must_cover_the_minimum() {
+        let mut contract = setup(min_balance() - 1);
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn registration_only_keeps_the_minimum() {
+        let mut contract = setup(min_balance() * 3);
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min_balance());
+
+        // already registered, the whole deposit is refunded
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min_balance());
+    }
+
+    #[test]
+    fn charged_bytes_are_not_available() {
+        let mut contract = setup(min_balance());
+        contract.storage_deposit(None, None);
+
+        contract.charge_storage(&accounts(1), 400);
+        let balance = contract.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.total.0, min_balance());
+        assert_eq!(balance.available.0, 600 * env::storage_byte_cost());
+
+        // released bytes never go below zero
+        contract.release_storage(&accounts(1), 500);
+        let balance = contract.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.available.0, min_balance());
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough storage deposit")]
+    fn charge_above_the_deposit_fails() {
+        let mut contract = setup(min_balance());
+        contract.storage_deposit(None, None);
+
+        contract.charge_storage(&accounts(1), LISTING_STORAGE_ESTIMATE);
+        contract.charge_storage(&accounts(1), 1);
+    }
+
+    #[test]
+    fn unregistered_accounts_release_nothing() {
+        let mut contract = setup(0);
+        contract.release_storage(&accounts(1), 100);
+        assert!(contract.storage_used.get(&accounts(1)).is_none());
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+    }
+}